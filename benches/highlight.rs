@@ -0,0 +1,33 @@
+//! Compares keyword lookup in hash sets with the linear scan it replaced:
+//!
+//! ```text
+//! cargo bench --bench highlight --features linear-keyword-scan -- --save-baseline linear
+//! cargo bench --bench highlight -- --baseline linear
+//! ```
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use hecto::{FileType, Row};
+
+/// Highlights every row of a Rust file several thousand lines long.
+fn highlight_rust_file(c: &mut Criterion) {
+    let source = include_str!("../src/row.rs");
+    let mut lines = Vec::new();
+    while lines.len() < 5000 {
+        lines.extend(source.lines());
+    }
+    let mut rows: Vec<Row> = lines.into_iter().map(Row::from).collect();
+    let file_type = FileType::detect("bench.rs", None);
+    let opts = file_type.highlighting_options();
+    c.bench_function("highlight rust file", |b| {
+        b.iter(|| {
+            let mut state = Default::default();
+            for row in &mut rows {
+                row.is_highlighted = false;
+                state = row.highlight(opts, &None, state);
+            }
+            black_box(state)
+        })
+    });
+}
+
+criterion_group!(benches, highlight_rust_file);
+criterion_main!(benches);
@@ -0,0 +1,291 @@
+use crate::terminal::{Cell, Style};
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::io::{self, stdout, ErrorKind, Write};
+use std::panic;
+use std::rc::Rc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Once;
+use std::time::Duration;
+
+use crossterm::cursor::{Hide, MoveTo, Show};
+use crossterm::event::{poll, read, Event, KeyCode, KeyEvent, KeyModifiers};
+use crossterm::event::{DisableBracketedPaste, EnableBracketedPaste};
+use crossterm::style::{Attribute, Color, Print, ResetColor, SetAttribute};
+use crossterm::style::{SetBackgroundColor, SetForegroundColor};
+use crossterm::terminal::{disable_raw_mode, enable_raw_mode};
+use crossterm::terminal::{Clear, ClearType, EnterAlternateScreen, LeaveAlternateScreen};
+use crossterm::{execute, queue};
+use unicode_width::UnicodeWidthStr;
+
+/// Everything `Terminal` needs from the outside world: the screen size, a
+/// place to draw cells and a source of input events.
+pub trait Backend {
+    fn size(&self) -> Result<(u16, u16), io::Error>;
+    fn clear(&mut self) -> Result<(), io::Error>;
+    /// Draws the given cells; positions are `(x, y)` screen coordinates.
+    fn draw(&mut self, content: &[(u16, u16, &Cell)]) -> Result<(), io::Error>;
+    fn hide_cursor(&mut self) -> Result<(), io::Error>;
+    fn show_cursor(&mut self) -> Result<(), io::Error>;
+    fn set_cursor(&mut self, x: u16, y: u16) -> Result<(), io::Error>;
+    fn flush(&mut self) -> Result<(), io::Error>;
+    /// Waits up to `timeout` for an event to become available.
+    fn poll(&mut self, timeout: Duration) -> Result<bool, io::Error>;
+    fn read_event(&mut self) -> Result<Event, io::Error>;
+}
+
+/// Set while the terminal is in raw mode on the alternate screen, so that
+/// the panic hook and `Drop` only restore it once.
+static ACTIVE: AtomicBool = AtomicBool::new(false);
+
+/// Makes sure the panic hook is installed only once, however many backends
+/// are created.
+static PANIC_HOOK: Once = Once::new();
+
+/// The real terminal. Creating it enters raw mode and the alternate screen;
+/// the user's terminal is restored when it is dropped or when the editor
+/// panics. Output is collected and written to stdout on `flush`.
+pub struct CrosstermBackend {
+    out: Vec<u8>,
+}
+
+impl CrosstermBackend {
+    pub fn new() -> Result<Self, io::Error> {
+        Self::install_panic_hook();
+        enable_raw_mode()?;
+        ACTIVE.store(true, Ordering::SeqCst);
+        execute!(stdout(), EnterAlternateScreen, EnableBracketedPaste)?;
+        Ok(Self { out: Vec::new() })
+    }
+    /// Restores cooked mode and the cursor before the default hook prints
+    /// the panic message, so it ends up on the user's normal screen.
+    fn install_panic_hook() {
+        PANIC_HOOK.call_once(|| {
+            let default_hook = panic::take_hook();
+            panic::set_hook(Box::new(move |info| {
+                Self::restore();
+                default_hook(info);
+            }));
+        });
+    }
+    fn restore() {
+        if ACTIVE.swap(false, Ordering::SeqCst) {
+            let _ = execute!(
+                stdout(),
+                ResetColor,
+                Show,
+                DisableBracketedPaste,
+                LeaveAlternateScreen
+            );
+            let _ = disable_raw_mode();
+        }
+    }
+}
+
+impl Backend for CrosstermBackend {
+    fn size(&self) -> Result<(u16, u16), io::Error> {
+        crossterm::terminal::size()
+    }
+    fn clear(&mut self) -> Result<(), io::Error> {
+        queue!(self.out, ResetColor, Clear(ClearType::All))
+    }
+    fn draw(&mut self, content: &[(u16, u16, &Cell)]) -> Result<(), io::Error> {
+        let mut style = Style::default();
+        let mut next_position = None;
+        for &(x, y, cell) in content {
+            if cell.symbol.is_empty() {
+                continue;
+            }
+            if next_position != Some((x, y)) {
+                queue!(self.out, MoveTo(x, y))?;
+            }
+            if (cell.style.bold, cell.style.italic, cell.style.underline)
+                != (style.bold, style.italic, style.underline)
+            {
+                // Resetting the attributes resets the colors as well.
+                queue!(self.out, SetAttribute(Attribute::Reset))?;
+                style = Style::default();
+                if cell.style.bold {
+                    queue!(self.out, SetAttribute(Attribute::Bold))?;
+                }
+                if cell.style.italic {
+                    queue!(self.out, SetAttribute(Attribute::Italic))?;
+                }
+                if cell.style.underline {
+                    queue!(self.out, SetAttribute(Attribute::Underlined))?;
+                }
+            }
+            if cell.style.fg != style.fg {
+                queue!(self.out, SetForegroundColor(cell.style.fg.unwrap_or(Color::Reset)))?;
+            }
+            if cell.style.bg != style.bg {
+                queue!(self.out, SetBackgroundColor(cell.style.bg.unwrap_or(Color::Reset)))?;
+            }
+            style = cell.style;
+            queue!(self.out, Print(&cell.symbol))?;
+            #[allow(clippy::cast_possible_truncation)]
+            let width = cell.symbol.width() as u16;
+            next_position = Some((x.saturating_add(width), y));
+        }
+        queue!(self.out, SetAttribute(Attribute::Reset), ResetColor)
+    }
+    fn hide_cursor(&mut self) -> Result<(), io::Error> {
+        queue!(self.out, Hide)
+    }
+    fn show_cursor(&mut self) -> Result<(), io::Error> {
+        queue!(self.out, Show)
+    }
+    fn set_cursor(&mut self, x: u16, y: u16) -> Result<(), io::Error> {
+        queue!(self.out, MoveTo(x, y))
+    }
+    fn flush(&mut self) -> Result<(), io::Error> {
+        let mut stdout = io::stdout();
+        stdout.write_all(&self.out)?;
+        self.out.clear();
+        stdout.flush()
+    }
+    fn poll(&mut self, timeout: Duration) -> Result<bool, io::Error> {
+        poll(timeout)
+    }
+    fn read_event(&mut self) -> Result<Event, io::Error> {
+        read()
+    }
+}
+
+impl Drop for CrosstermBackend {
+    fn drop(&mut self) {
+        Self::restore();
+    }
+}
+
+struct TestScreen {
+    width: u16,
+    height: u16,
+    cells: Vec<Cell>,
+    cursor: (u16, u16),
+    cursor_visible: bool,
+    events: VecDeque<Event>,
+}
+
+/// A headless backend that records drawn cells in a grid and replays
+/// scripted events. Clones share the same screen and event queue, so a test
+/// can keep a handle after passing one to `Terminal`.
+#[derive(Clone)]
+pub struct TestBackend {
+    screen: Rc<RefCell<TestScreen>>,
+}
+
+impl TestBackend {
+    pub fn new(width: u16, height: u16) -> Self {
+        Self {
+            screen: Rc::new(RefCell::new(TestScreen {
+                width,
+                height,
+                cells: vec![Cell::default(); usize::from(width) * usize::from(height)],
+                cursor: (0, 0),
+                cursor_visible: true,
+                events: VecDeque::new(),
+            })),
+        }
+    }
+    /// Changes the screen size and queues the matching resize event.
+    pub fn resize(&self, width: u16, height: u16) {
+        {
+            let mut screen = self.screen.borrow_mut();
+            screen.width = width;
+            screen.height = height;
+            screen.cells = vec![Cell::default(); usize::from(width) * usize::from(height)];
+        }
+        self.push_event(Event::Resize(width, height));
+    }
+    pub fn push_event(&self, event: Event) {
+        self.screen.borrow_mut().events.push_back(event);
+    }
+    pub fn push_key(&self, code: KeyCode, modifiers: KeyModifiers) {
+        self.push_event(Event::Key(KeyEvent::new(code, modifiers)));
+    }
+    /// Queues one key event per character of `text`.
+    pub fn type_str(&self, text: &str) {
+        for c in text.chars() {
+            self.push_key(KeyCode::Char(c), KeyModifiers::NONE);
+        }
+    }
+    pub fn pending_events(&self) -> usize {
+        self.screen.borrow().events.len()
+    }
+    pub fn cell(&self, x: u16, y: u16) -> Cell {
+        let screen = self.screen.borrow();
+        screen.cells[usize::from(y) * usize::from(screen.width) + usize::from(x)].clone()
+    }
+    /// Returns the text of screen line `y`, including trailing blanks.
+    pub fn line(&self, y: u16) -> String {
+        let screen = self.screen.borrow();
+        let start = usize::from(y) * usize::from(screen.width);
+        screen.cells[start..start + usize::from(screen.width)]
+            .iter()
+            .map(|cell| cell.symbol.as_str())
+            .collect()
+    }
+    pub fn lines(&self) -> Vec<String> {
+        let height = self.screen.borrow().height;
+        (0..height).map(|y| self.line(y)).collect()
+    }
+    pub fn cursor(&self) -> (u16, u16) {
+        self.screen.borrow().cursor
+    }
+    pub fn cursor_visible(&self) -> bool {
+        self.screen.borrow().cursor_visible
+    }
+}
+
+impl Backend for TestBackend {
+    fn size(&self) -> Result<(u16, u16), io::Error> {
+        let screen = self.screen.borrow();
+        Ok((screen.width, screen.height))
+    }
+    fn clear(&mut self) -> Result<(), io::Error> {
+        for cell in &mut self.screen.borrow_mut().cells {
+            *cell = Cell::default();
+        }
+        Ok(())
+    }
+    fn draw(&mut self, content: &[(u16, u16, &Cell)]) -> Result<(), io::Error> {
+        let mut screen = self.screen.borrow_mut();
+        let width = usize::from(screen.width);
+        for &(x, y, cell) in content {
+            let index = usize::from(y) * width + usize::from(x);
+            screen.cells[index] = cell.clone();
+            for covered in 1..cell.symbol.width() {
+                if let Some(covered_cell) = screen.cells.get_mut(index + covered) {
+                    covered_cell.symbol = String::new();
+                }
+            }
+        }
+        Ok(())
+    }
+    fn hide_cursor(&mut self) -> Result<(), io::Error> {
+        self.screen.borrow_mut().cursor_visible = false;
+        Ok(())
+    }
+    fn show_cursor(&mut self) -> Result<(), io::Error> {
+        self.screen.borrow_mut().cursor_visible = true;
+        Ok(())
+    }
+    fn set_cursor(&mut self, x: u16, y: u16) -> Result<(), io::Error> {
+        self.screen.borrow_mut().cursor = (x, y);
+        Ok(())
+    }
+    fn flush(&mut self) -> Result<(), io::Error> {
+        Ok(())
+    }
+    fn poll(&mut self, _timeout: Duration) -> Result<bool, io::Error> {
+        Ok(!self.screen.borrow().events.is_empty())
+    }
+    fn read_event(&mut self) -> Result<Event, io::Error> {
+        self.screen
+            .borrow_mut()
+            .events
+            .pop_front()
+            .ok_or_else(|| io::Error::new(ErrorKind::UnexpectedEof, "no more scripted events"))
+    }
+}
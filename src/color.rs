@@ -0,0 +1,189 @@
+use crossterm::style::Color;
+use std::env;
+
+/// The 16 standard ANSI colors with their usual xterm RGB values.
+const ANSI_16: [(Color, (u8, u8, u8)); 16] = [
+    (Color::Black, (0, 0, 0)),
+    (Color::DarkRed, (205, 0, 0)),
+    (Color::DarkGreen, (0, 205, 0)),
+    (Color::DarkYellow, (205, 205, 0)),
+    (Color::DarkBlue, (0, 0, 238)),
+    (Color::DarkMagenta, (205, 0, 205)),
+    (Color::DarkCyan, (0, 205, 205)),
+    (Color::Grey, (229, 229, 229)),
+    (Color::DarkGrey, (127, 127, 127)),
+    (Color::Red, (255, 0, 0)),
+    (Color::Green, (0, 255, 0)),
+    (Color::Yellow, (255, 255, 0)),
+    (Color::Blue, (92, 92, 255)),
+    (Color::Magenta, (255, 0, 255)),
+    (Color::Cyan, (0, 255, 255)),
+    (Color::White, (255, 255, 255)),
+];
+
+/// Channel levels of the 6x6x6 color cube in the 256-color palette.
+const CUBE_LEVELS: [u8; 6] = [0, 95, 135, 175, 215, 255];
+
+/// How many colors the terminal can display.
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum ColorSupport {
+    TrueColor,
+    Ansi256,
+    Ansi16,
+}
+
+impl ColorSupport {
+    /// Detects the color support from the environment. `HECTO_COLORS`
+    /// (`truecolor`, `256` or `16`) overrides what `COLORTERM` and `TERM`
+    /// suggest.
+    pub fn detect() -> Self {
+        Self::from_env(
+            env::var("HECTO_COLORS").ok().as_deref(),
+            env::var("COLORTERM").ok().as_deref(),
+            env::var("TERM").ok().as_deref(),
+        )
+    }
+    pub fn from_env(hecto_colors: Option<&str>, colorterm: Option<&str>, term: Option<&str>) -> Self {
+        if let Some(support) = hecto_colors.and_then(Self::from_name) {
+            return support;
+        }
+        if let Some("truecolor" | "24bit") = colorterm {
+            return Self::TrueColor;
+        }
+        match term {
+            Some(term) if term.ends_with("-direct") => Self::TrueColor,
+            Some(term) if term.contains("256color") => Self::Ansi256,
+            _ => Self::Ansi16,
+        }
+    }
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "truecolor" | "24bit" => Some(Self::TrueColor),
+            "256" | "ansi256" => Some(Self::Ansi256),
+            "16" | "ansi16" => Some(Self::Ansi16),
+            _ => None,
+        }
+    }
+    /// Maps `color` to the nearest color the terminal can display.
+    pub fn quantize(self, color: Color) -> Color {
+        match (self, color) {
+            (Self::TrueColor, _) => color,
+            (Self::Ansi256, Color::Rgb { r, g, b }) => Color::AnsiValue(to_ansi_256(r, g, b)),
+            (Self::Ansi16, Color::Rgb { r, g, b }) => to_ansi_16(r, g, b),
+            (Self::Ansi16, Color::AnsiValue(value)) => {
+                let (r, g, b) = ansi_256_to_rgb(value);
+                to_ansi_16(r, g, b)
+            }
+            _ => color,
+        }
+    }
+}
+
+#[allow(clippy::integer_arithmetic, clippy::as_conversions)]
+fn distance((r1, g1, b1): (u8, u8, u8), (r2, g2, b2): (u8, u8, u8)) -> u32 {
+    let dr = (i32::from(r1) - i32::from(r2)).pow(2) as u32;
+    let dg = (i32::from(g1) - i32::from(g2)).pow(2) as u32;
+    let db = (i32::from(b1) - i32::from(b2)).pow(2) as u32;
+    2 * dr + 4 * dg + 3 * db
+}
+
+#[allow(clippy::cast_possible_truncation, clippy::as_conversions)]
+fn nearest_cube_index(value: u8) -> u8 {
+    CUBE_LEVELS
+        .iter()
+        .enumerate()
+        .min_by_key(|(_, level)| (i16::from(**level) - i16::from(value)).abs())
+        .map_or(0, |(index, _)| index as u8)
+}
+
+#[allow(clippy::integer_arithmetic, clippy::integer_division)]
+fn to_ansi_256(r: u8, g: u8, b: u8) -> u8 {
+    let (ri, gi, bi) = (nearest_cube_index(r), nearest_cube_index(g), nearest_cube_index(b));
+    let cube = (
+        CUBE_LEVELS[usize::from(ri)],
+        CUBE_LEVELS[usize::from(gi)],
+        CUBE_LEVELS[usize::from(bi)],
+    );
+    let cube_index = 16 + 36 * ri + 6 * gi + bi;
+
+    let average = (u16::from(r) + u16::from(g) + u16::from(b)) / 3;
+    let gray_step = if average < 8 {
+        0
+    } else {
+        ((average - 8) / 10).min(23)
+    };
+    #[allow(clippy::cast_possible_truncation, clippy::as_conversions)]
+    let gray_step = gray_step as u8;
+    let gray_level = 8 + 10 * gray_step;
+    if distance((gray_level, gray_level, gray_level), (r, g, b)) < distance(cube, (r, g, b)) {
+        232 + gray_step
+    } else {
+        cube_index
+    }
+}
+
+#[allow(clippy::integer_arithmetic, clippy::integer_division, clippy::indexing_slicing)]
+fn ansi_256_to_rgb(value: u8) -> (u8, u8, u8) {
+    match value {
+        0..=15 => ANSI_16[usize::from(value)].1,
+        16..=231 => {
+            let index = value - 16;
+            (
+                CUBE_LEVELS[usize::from(index / 36)],
+                CUBE_LEVELS[usize::from(index / 6 % 6)],
+                CUBE_LEVELS[usize::from(index % 6)],
+            )
+        }
+        _ => {
+            let level = 8 + 10 * (value - 232);
+            (level, level, level)
+        }
+    }
+}
+
+fn to_ansi_16(r: u8, g: u8, b: u8) -> Color {
+    ANSI_16
+        .iter()
+        .min_by_key(|(_, rgb)| distance(*rgb, (r, g, b)))
+        .map_or(Color::Reset, |(color, _)| *color)
+}
+
+#[cfg(test)]
+mod test_super {
+    use super::*;
+
+    #[test]
+    fn test_detect() {
+        assert_eq!(
+            ColorSupport::from_env(None, Some("truecolor"), Some("xterm-256color")),
+            ColorSupport::TrueColor
+        );
+        assert_eq!(
+            ColorSupport::from_env(None, None, Some("screen-256color")),
+            ColorSupport::Ansi256
+        );
+        assert_eq!(ColorSupport::from_env(None, None, Some("linux")), ColorSupport::Ansi16);
+        assert_eq!(
+            ColorSupport::from_env(Some("16"), Some("truecolor"), None),
+            ColorSupport::Ansi16
+        );
+    }
+
+    #[test]
+    fn test_quantize() {
+        let sky = Color::Rgb { r: 135, g: 206, b: 235 };
+        assert_eq!(ColorSupport::TrueColor.quantize(sky), sky);
+        assert_eq!(ColorSupport::Ansi256.quantize(sky), Color::AnsiValue(116));
+        assert_eq!(
+            ColorSupport::Ansi256.quantize(Color::Rgb { r: 63, g: 63, b: 63 }),
+            Color::AnsiValue(237)
+        );
+        assert_eq!(ColorSupport::Ansi16.quantize(sky), Color::Grey);
+        assert_eq!(
+            ColorSupport::Ansi16.quantize(Color::Rgb { r: 211, g: 54, b: 130 }),
+            Color::DarkMagenta
+        );
+        assert_eq!(ColorSupport::Ansi16.quantize(Color::AnsiValue(196)), Color::Red);
+        assert_eq!(ColorSupport::Ansi16.quantize(Color::Reset), Color::Reset);
+    }
+}
@@ -0,0 +1,310 @@
+use crate::ColorSupport;
+use serde::de::DeserializeOwned;
+use std::collections::BTreeMap;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Name of the per-project configuration file, looked up in the current
+/// directory.
+pub const PROJECT_FILE_NAME: &str = ".hecto.toml";
+
+/// Settings that can be changed while editing, e.g. with `:set`.
+pub const OPTIONS: [&str; 13] = [
+    "tab_width",
+    "line_numbers",
+    "theme",
+    "colors",
+    "soft_wrap",
+    "vim",
+    "autosave",
+    "quit_times",
+    "message_timeout",
+    "cursor_line",
+    "rulers",
+    "visible_whitespace",
+    "trailing_whitespace",
+];
+
+/// Editor settings, read from `~/.config/hecto/config.toml` and then from
+/// `.hecto.toml` in the current directory, later files overriding earlier
+/// ones.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Config {
+    pub tab_width: usize,
+    pub line_numbers: bool,
+    /// Name of a built-in theme, a theme in the config directory or a path.
+    pub theme: String,
+    /// Overrides the detected color support.
+    pub colors: Option<ColorSupport>,
+    pub soft_wrap: bool,
+    /// Use the modal Vim keymap on top of the regular bindings.
+    pub vim: bool,
+    /// Seconds without input after which a modified file is saved; 0 turns
+    /// autosave off.
+    pub autosave: u64,
+    pub quit_times: u8,
+    /// Seconds a message stays in the message bar.
+    pub message_timeout: u64,
+    /// Highlight the row the cursor is on.
+    pub cursor_line: bool,
+    /// Columns, counted from 1, drawn as vertical rulers, e.g. 81 for a
+    /// ruler right after the 80th column.
+    pub rulers: Vec<usize>,
+    /// Show tabs, trailing spaces, non-breaking spaces and full-width
+    /// spaces.
+    pub visible_whitespace: bool,
+    /// Highlight trailing whitespace, whether or not whitespace is visible.
+    pub trailing_whitespace: bool,
+    pub log_file: String,
+    /// Key chords mapped to command names.
+    pub keybindings: BTreeMap<String, String>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            tab_width: 4,
+            line_numbers: false,
+            theme: String::from("dark"),
+            colors: None,
+            soft_wrap: false,
+            vim: false,
+            autosave: 0,
+            quit_times: 3,
+            message_timeout: 5,
+            cursor_line: false,
+            rulers: Vec::new(),
+            visible_whitespace: false,
+            trailing_whitespace: false,
+            log_file: String::from("log/app.log"),
+            keybindings: BTreeMap::new(),
+        }
+    }
+}
+
+impl Config {
+    /// Loads the user and project configuration files. Problems with either
+    /// file are returned as messages next to a config that keeps every
+    /// setting that could be read.
+    pub fn load() -> (Self, Vec<String>) {
+        let mut config = Self::default();
+        let mut errors = Vec::new();
+        let mut paths = Vec::new();
+        if let Some(dir) = config_dir() {
+            paths.push(dir.join("config.toml"));
+        }
+        paths.push(PathBuf::from(PROJECT_FILE_NAME));
+        for path in paths {
+            if !path.is_file() {
+                continue;
+            }
+            match fs::read_to_string(&path) {
+                Ok(source) => {
+                    for error in config.merge_toml(&source) {
+                        errors.push(format!("{}: {}", path.display(), error));
+                    }
+                }
+                Err(error) => errors.push(format!("{}: {}", path.display(), error)),
+            }
+        }
+        (config, errors)
+    }
+    /// Applies the settings in `source` on top of this config. Invalid
+    /// settings are skipped and reported.
+    pub fn merge_toml(&mut self, source: &str) -> Vec<String> {
+        let table: toml::value::Table = match toml::from_str(source) {
+            Ok(table) => table,
+            Err(error) => return vec![error.to_string()],
+        };
+        table
+            .into_iter()
+            .filter_map(|(name, value)| self.apply(&name, value).err())
+            .collect()
+    }
+    /// Changes the setting `name` to `value`, leaving it as it was if the
+    /// value is invalid.
+    fn apply(&mut self, name: &str, value: toml::Value) -> Result<(), String> {
+        match name {
+            "tab_width" => {
+                let tab_width = read(name, value)?;
+                if !(1..=16).contains(&tab_width) {
+                    return Err(String::from("tab_width must be between 1 and 16"));
+                }
+                self.tab_width = tab_width;
+            }
+            "colors" => {
+                let colors: String = read(name, value)?;
+                self.colors = Some(ColorSupport::from_name(&colors).ok_or_else(|| {
+                    format!(
+                        "colors must be one of truecolor, 256 or 16, not `{}`",
+                        colors
+                    )
+                })?);
+            }
+            "line_numbers" => self.line_numbers = read(name, value)?,
+            "theme" => self.theme = read(name, value)?,
+            "soft_wrap" => self.soft_wrap = read(name, value)?,
+            "vim" => self.vim = read(name, value)?,
+            "autosave" => self.autosave = read(name, value)?,
+            "quit_times" => self.quit_times = read(name, value)?,
+            "message_timeout" => self.message_timeout = read(name, value)?,
+            "cursor_line" => self.cursor_line = read(name, value)?,
+            "rulers" => {
+                let rulers: Vec<usize> = read(name, value)?;
+                if rulers.contains(&0) {
+                    return Err(String::from("rulers count columns from 1"));
+                }
+                self.rulers = rulers;
+            }
+            "visible_whitespace" => self.visible_whitespace = read(name, value)?,
+            "trailing_whitespace" => self.trailing_whitespace = read(name, value)?,
+            "log_file" => self.log_file = read(name, value)?,
+            "keybindings" => {
+                let keybindings: BTreeMap<String, String> = read(name, value)?;
+                self.keybindings.extend(keybindings);
+            }
+            _ => return Err(format!("unknown setting `{}`", name)),
+        }
+        Ok(())
+    }
+    /// Changes one of the `OPTIONS`, with the value written without quotes
+    /// or brackets and lists separated by commas. A missing value turns a
+    /// boolean option on.
+    pub fn set(&mut self, name: &str, value: Option<&str>) -> Result<(), String> {
+        let text = value.unwrap_or("true");
+        let value = match name {
+            "line_numbers"
+            | "soft_wrap"
+            | "vim"
+            | "cursor_line"
+            | "visible_whitespace"
+            | "trailing_whitespace" => text
+                .parse()
+                .map(toml::Value::Boolean)
+                .map_err(|_| format!("{} must be true or false, not `{}`", name, text))?,
+            "tab_width" | "autosave" | "quit_times" | "message_timeout" => {
+                parse_number(name, text)?
+            }
+            "rulers" => toml::Value::Array(
+                text.split(',')
+                    .map(str::trim)
+                    .filter(|column| !column.is_empty())
+                    .map(|column| parse_number(name, column))
+                    .collect::<Result<_, _>>()?,
+            ),
+            "theme" | "colors" => toml::Value::String(text.to_string()),
+            _ => return Err(format!("unknown option `{}`", name)),
+        };
+        self.apply(name, value)
+    }
+}
+
+/// Parses a number given to the option `name`.
+fn parse_number(name: &str, text: &str) -> Result<toml::Value, String> {
+    text.trim()
+        .parse()
+        .map(toml::Value::Integer)
+        .map_err(|_| format!("{} must be a number, not `{}`", name, text))
+}
+
+/// Reads `value` as the type of the setting `name`.
+fn read<T: DeserializeOwned>(name: &str, value: toml::Value) -> Result<T, String> {
+    value
+        .try_into()
+        .map_err(|error| format!("{} for key `{}`", error, name))
+}
+
+/// `$XDG_CONFIG_HOME/hecto`, falling back to `~/.config/hecto`.
+pub fn config_dir() -> Option<PathBuf> {
+    let base = match env::var_os("XDG_CONFIG_HOME") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => Path::new(&env::var_os("HOME")?).join(".config"),
+    };
+    Some(base.join("hecto"))
+}
+
+#[cfg(test)]
+mod test_super {
+    use super::*;
+
+    #[test]
+    fn test_merge_toml() {
+        let mut config = Config::default();
+        let errors = config.merge_toml(
+            "tab_width = 8\nline_numbers = true\ncolors = \"256\"\n\
+             [keybindings]\n\"Ctrl-K Ctrl-S\" = \"save\"\n",
+        );
+        assert!(errors.is_empty());
+        assert_eq!(config.tab_width, 8);
+        assert!(config.line_numbers);
+        assert_eq!(config.colors, Some(ColorSupport::Ansi256));
+        assert_eq!(config.keybindings["Ctrl-K Ctrl-S"], "save");
+
+        let errors = config.merge_toml("tab_width = 0\ntheme = \"light\"\n");
+        assert_eq!(errors, vec!["tab_width must be between 1 and 16"]);
+        assert_eq!(config.tab_width, 8);
+        assert_eq!(config.theme, "light");
+
+        let errors = config.merge_toml(
+            "tab_width = 2\nline_numbers = false\nvisible_whitspace = true\n\
+             rulers = \"80\"\n",
+        );
+        assert_eq!(errors.len(), 2);
+        assert!(errors
+            .iter()
+            .any(|e| e == "unknown setting `visible_whitspace`"));
+        assert!(errors.iter().any(|e| e.ends_with("for key `rulers`")));
+        assert_eq!(config.tab_width, 2);
+        assert!(!config.line_numbers);
+        assert!(config.rulers.is_empty());
+
+        assert_eq!(config.merge_toml("tab_width = ").len(), 1);
+    }
+
+    #[test]
+    fn test_set() {
+        let mut config = Config::default();
+        assert!(config.set("line_numbers", None).is_ok());
+        assert!(config.line_numbers);
+        assert!(config.set("tab_width", Some("2")).is_ok());
+        assert_eq!(config.tab_width, 2);
+        assert!(config.set("colors", Some("256")).is_ok());
+        assert_eq!(config.colors, Some(ColorSupport::Ansi256));
+        assert!(config.set("theme", Some("my theme")).is_ok());
+        assert_eq!(config.theme, "my theme");
+        assert_eq!(
+            config.set("tab_width", Some("99")),
+            Err(String::from("tab_width must be between 1 and 16"))
+        );
+        assert_eq!(
+            config.set("tab_width", Some("wide")),
+            Err(String::from("tab_width must be a number, not `wide`"))
+        );
+        assert_eq!(
+            config.set("colors", Some("bogus")),
+            Err(String::from(
+                "colors must be one of truecolor, 256 or 16, not `bogus`"
+            ))
+        );
+        assert_eq!(
+            config.set("soft_wrap", Some("maybe")),
+            Err(String::from("soft_wrap must be true or false, not `maybe`"))
+        );
+        assert_eq!(
+            config.set("log_file", Some("x")),
+            Err(String::from("unknown option `log_file`"))
+        );
+        assert!(config.set("rulers", Some("80,100")).is_ok());
+        assert_eq!(config.rulers, [80, 100]);
+        assert!(config.set("rulers", Some("80,x")).is_err());
+        assert_eq!(
+            config.set("rulers", Some("0")),
+            Err(String::from("rulers count columns from 1"))
+        );
+        assert_eq!(config.rulers, [80, 100]);
+        assert!(config.set("rulers", Some("")).is_ok());
+        assert!(config.rulers.is_empty());
+        assert_eq!(config.tab_width, 2);
+    }
+}
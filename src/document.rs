@@ -1,22 +1,57 @@
+use crate::highlighting;
+#[cfg(feature = "tree-sitter")]
+use crate::treesitter::SyntaxTree;
 use crate::FileType;
 use crate::Position;
+use crate::row::DEFAULT_TAB_WIDTH;
 use crate::Row;
 use crate::SearchDirection;
 use std::fs;
 use std::io::{Error, Write};
+#[cfg(feature = "tree-sitter")]
+use tree_sitter::{InputEdit, Point};
+#[cfg(feature = "tree-sitter")]
+use unicode_segmentation::UnicodeSegmentation;
 
-#[derive(Default)]
 pub struct Document {
     pub rows: Vec<Row>,
     pub file_name: Option<String>,
     dirty: bool,
     file_type: FileType,
+    /// Display width of a tab stop in every row.
+    tab_width: usize,
+    /// Rows before this one are highlighted from the right start state.
+    highlighted_until: usize,
+    /// Whether the syntax tree was parsed from the current rows.
+    #[cfg(feature = "tree-sitter")]
+    parsed: bool,
+    /// A full parse of the document, used instead of `file_type` to
+    /// highlight languages that tree-sitter knows.
+    #[cfg(feature = "tree-sitter")]
+    syntax_tree: Option<SyntaxTree>,
+}
+
+impl Default for Document {
+    fn default() -> Self {
+        Self {
+            rows: Vec::new(),
+            file_name: None,
+            dirty: false,
+            file_type: FileType::default(),
+            tab_width: DEFAULT_TAB_WIDTH,
+            highlighted_until: 0,
+            #[cfg(feature = "tree-sitter")]
+            parsed: false,
+            #[cfg(feature = "tree-sitter")]
+            syntax_tree: None,
+        }
+    }
 }
 
 impl Document {
     pub fn open(filename: &str) -> Result<Self, std::io::Error> {
         let contents = fs::read_to_string(filename)?;
-        let file_type = FileType::from(filename);
+        let file_type = FileType::detect(filename, contents.lines().next());
         let mut rows = Vec::new();
         for value in contents.lines() {
             rows.push(Row::from(value));
@@ -25,9 +60,23 @@ impl Document {
             rows,
             file_name: Some(filename.to_string()),
             dirty: false,
+            tab_width: DEFAULT_TAB_WIDTH,
+            highlighted_until: 0,
+            #[cfg(feature = "tree-sitter")]
+            parsed: false,
+            #[cfg(feature = "tree-sitter")]
+            syntax_tree: SyntaxTree::for_file_type(&file_type.name()),
             file_type,
         })
     }
+    /// Changes the tab stop width and recomputes the column layout of every
+    /// row.
+    pub fn set_tab_width(&mut self, tab_width: usize) {
+        self.tab_width = tab_width;
+        for row in &mut self.rows {
+            row.set_tab_width(tab_width);
+        }
+    }
     pub fn file_type(&self) -> String {
         self.file_type.name()
     }
@@ -45,15 +94,19 @@ impl Document {
             return;
         }
         if at.y == self.rows.len() {
-            self.rows.push(Row::default());
+            let (first, count) = self.last_row();
+            let edit = self.start_edit(first, count);
+            self.rows.push(Row::new("", self.tab_width));
+            self.finish_edit(edit, count.saturating_add(1));
             return;
         }
+        let edit = self.start_edit(at.y, 1);
         #[allow(clippy::indexing_slicing)]
             let current_row = &mut self.rows[at.y];
         let new_row = current_row.split(at.x_word_index);
-        // #[allow(clippy::integer_arithmetic)]
-        current_row.word_width_index = current_row.word_width_index[..at.x_word_index.clone()].to_vec();
         self.rows.insert(at.y + 1, new_row);
+        self.finish_edit(edit, 2);
+        self.unhighlight_rows(at.y, at.y.saturating_add(2));
     }
     pub fn insert(&mut self, at: &mut Position, c: char) {
         if at.y > self.rows.len() {
@@ -64,24 +117,208 @@ impl Document {
         //     self.insert_newline(at);
         // } else
         if at.y == self.rows.len() {
-            let mut row = Row::default();
+            let (first, count) = self.last_row();
+            let edit = self.start_edit(first, count);
+            let mut row = Row::new("", self.tab_width);
             row.insert(0, c);
             self.rows.push(row);
+            self.finish_edit(edit, count.saturating_add(1));
         } else {
+            let edit = self.start_edit(at.y, 1);
             #[allow(clippy::indexing_slicing)]
                 let row = &mut self.rows[at.y];
             row.insert(at.x_word_index, c);
+            self.finish_edit(edit, 1);
         }
         // at.x_word_index += 1;
-        self.unhighlight_rows(at.y);
+        self.unhighlight_rows(at.y, at.y.saturating_add(1));
     }
 
-    fn unhighlight_rows(&mut self, start: usize) {
-        let start = start.saturating_sub(1);
-        for row in self.rows.iter_mut().skip(start) {
+    /// Inserts a (possibly multi-line) block of text as a single edit and
+    /// moves `at` to the end of the inserted text.
+    #[allow(clippy::indexing_slicing, clippy::integer_arithmetic)]
+    pub fn insert_str(&mut self, at: &mut Position, text: &str) {
+        if at.y > self.rows.len() || text.is_empty() {
+            return;
+        }
+        self.dirty = true;
+        let text = text.replace("\r\n", "\n").replace('\r', "\n");
+        let (first, count) = if at.y == self.rows.len() {
+            self.last_row()
+        } else {
+            (at.y, 1)
+        };
+        let edit = self.start_edit(first, count);
+        if at.y == self.rows.len() {
+            self.rows.push(Row::new("", self.tab_width));
+        }
+        let start = at.y;
+        let mut lines = text.split('\n');
+        let tab_width = self.tab_width;
+        let current_row = &mut self.rows[at.y];
+        let tail = current_row.split(at.x_word_index);
+        if let Some(first_line) = lines.next() {
+            current_row.append(&Row::from(first_line));
+        }
+        let new_rows: Vec<Row> = lines.map(|line| Row::new(line, tab_width)).collect();
+        at.y += new_rows.len();
+        self.rows.splice(start + 1..start + 1, new_rows);
+
+        let last_row = &mut self.rows[at.y];
+        at.x_word_index = last_row.word_width_index.len();
+        at.x = last_row.word_width_index.iter().sum();
+        last_row.append(&tail);
+        self.finish_edit(edit, at.y + 1 - first);
+        self.unhighlight_rows(start, at.y + 1);
+    }
+
+    /// Replaces the text of row `index`.
+    pub fn replace_row(&mut self, index: usize, text: &str) {
+        let tab_width = self.tab_width;
+        let edit = self.start_edit(index, 1);
+        if let Some(row) = self.rows.get_mut(index) {
+            *row = Row::new(text, tab_width);
+            self.finish_edit(edit, 1);
+            self.dirty = true;
+            self.unhighlight_rows(index, index.saturating_add(1));
+        }
+    }
+    /// Returns the text from `start` up to, not including, `end`, with rows
+    /// joined by newlines.
+    pub fn text(&self, start: &Position, end: &Position) -> String {
+        let mut lines = Vec::new();
+        for (y, row) in self.rows.iter().enumerate().take(end.y.saturating_add(1)).skip(start.y) {
+            let from = if y == start.y { start.x_word_index } else { 0 };
+            let to = if y == end.y { end.x_word_index } else { row.len() };
+            lines.push(row.slice(from, to));
+        }
+        lines.join("\n")
+    }
+    /// Deletes the text from `start` up to, not including, `end` as a single
+    /// edit.
+    #[allow(clippy::indexing_slicing)]
+    pub fn delete_range(&mut self, start: &Position, end: &Position) {
+        if start.y >= self.rows.len() || (start.y, start.x_word_index) >= (end.y, end.x_word_index) {
+            return;
+        }
+        self.dirty = true;
+        let last = end.y.min(self.rows.len().saturating_sub(1));
+        let tail = if end.y == last {
+            self.rows[last].slice(end.x_word_index, self.rows[last].len())
+        } else {
+            String::new()
+        };
+        let head = self.rows[start.y].slice(0, start.x_word_index);
+        let edit = self.start_edit(start.y, last.saturating_sub(start.y).saturating_add(1));
+        self.rows
+            .splice(start.y..=last, vec![Row::new(&format!("{}{}", head, tail), self.tab_width)]);
+        self.finish_edit(edit, 1);
+        self.unhighlight_rows(start.y, start.y.saturating_add(1));
+    }
+    /// Removes the rows `first..=last` and returns their text joined by
+    /// newlines.
+    pub fn delete_rows(&mut self, first: usize, last: usize) -> String {
+        if first >= self.rows.len() {
+            return String::new();
+        }
+        self.dirty = true;
+        let last = last.min(self.rows.len().saturating_sub(1));
+        // The edit takes in a row next to the removed ones, which loses the
+        // newline between them.
+        let count = last.saturating_sub(first).saturating_add(1);
+        let (edit_first, old_count, new_count) = if last.saturating_add(1) < self.rows.len() {
+            (first, count.saturating_add(1), 1)
+        } else if first > 0 {
+            (first.saturating_sub(1), count.saturating_add(1), 1)
+        } else {
+            (first, count, 0)
+        };
+        let edit = self.start_edit(edit_first, old_count);
+        let removed: Vec<String> = self.rows.drain(first..=last).map(|row| row.string).collect();
+        self.finish_edit(edit, new_count);
+        self.unhighlight_rows(first, first);
+        removed.join("\n")
+    }
+    /// Marks the rows `start..end` as changed. The rows after them keep
+    /// their highlighting unless they turn out to start in another state,
+    /// such as inside a comment opened by the change.
+    fn unhighlight_rows(&mut self, start: usize, end: usize) {
+        for row in self.rows.iter_mut().take(end).skip(start) {
             row.is_highlighted = false;
         }
+        self.highlighted_until = self.highlighted_until.min(start);
+        #[cfg(feature = "tree-sitter")]
+        {
+            self.parsed = false;
+        }
+    }
+    /// The last row, as the first row and row count of an edit that adds
+    /// rows at the end.
+    fn last_row(&self) -> (usize, usize) {
+        match self.rows.len().checked_sub(1) {
+            Some(last) => (last, 1),
+            None => (0, 0),
+        }
     }
+    /// Starts an edit that replaces the `count` rows from `first` on, for
+    /// the syntax tree to re-parse only around them.
+    #[cfg(feature = "tree-sitter")]
+    fn start_edit(&self, first: usize, count: usize) -> Option<InputEdit> {
+        self.syntax_tree.as_ref()?;
+        let start_byte = self
+            .rows
+            .iter()
+            .take(first)
+            .map(|row| row.string.len().saturating_add(1))
+            .sum();
+        let (old_end_byte, old_end_position) = self.rows_end(start_byte, first, count);
+        Some(InputEdit {
+            start_byte,
+            old_end_byte,
+            new_end_byte: old_end_byte,
+            start_position: Point { row: first, column: 0 },
+            old_end_position,
+            new_end_position: old_end_position,
+        })
+    }
+    /// Tells the syntax tree about an edit once the rows it replaced are
+    /// `count` rows.
+    #[cfg(feature = "tree-sitter")]
+    fn finish_edit(&mut self, edit: Option<InputEdit>, count: usize) {
+        if let Some(mut edit) = edit {
+            let (new_end_byte, new_end_position) =
+                self.rows_end(edit.start_byte, edit.start_position.row, count);
+            edit.new_end_byte = new_end_byte;
+            edit.new_end_position = new_end_position;
+            if let Some(syntax_tree) = &mut self.syntax_tree {
+                syntax_tree.edit(&edit);
+            }
+        }
+    }
+    /// The byte offset and position at which the `count` rows from `first`
+    /// end, where `start_byte` is the offset of row `first`.
+    #[cfg(feature = "tree-sitter")]
+    fn rows_end(&self, start_byte: usize, first: usize, count: usize) -> (usize, Point) {
+        let mut byte = start_byte;
+        let mut position = Point { row: first, column: 0 };
+        for (index, row) in self.rows.iter().enumerate().skip(first).take(count) {
+            if index > first {
+                byte = byte.saturating_add(1);
+            }
+            byte = byte.saturating_add(row.string.len());
+            position = Point {
+                row: index,
+                column: row.string.len(),
+            };
+        }
+        (byte, position)
+    }
+    #[cfg(not(feature = "tree-sitter"))]
+    fn start_edit(&self, _first: usize, _count: usize) -> Option<()> {
+        None
+    }
+    #[cfg(not(feature = "tree-sitter"))]
+    fn finish_edit(&mut self, _edit: Option<()>, _count: usize) {}
     #[allow(clippy::integer_arithmetic, clippy::indexing_slicing)]
     pub fn delete(&mut self, at: &Position) {
         let len = self.rows.len();
@@ -90,20 +327,33 @@ impl Document {
         }
         self.dirty = true;
         if at.x_word_index == self.rows[at.y].word_width_index.len() && at.y + 1 < len {
+            let edit = self.start_edit(at.y, 2);
             let next_row = self.rows.remove(at.y + 1);
             let row = &mut self.rows[at.y];
             row.append(&next_row);
+            self.finish_edit(edit, 1);
         } else {
+            let edit = self.start_edit(at.y, 1);
             let row = &mut self.rows[at.y];
             // row.delete(at.x);
             row.delete(at.x_word_index);
+            self.finish_edit(edit, 1);
         }
-        self.unhighlight_rows(at.y);
+        self.unhighlight_rows(at.y, at.y + 1);
     }
     pub fn save(&mut self) -> Result<(), Error> {
         if let Some(file_name) = &self.file_name {
             let mut file = fs::File::create(file_name)?;
-            self.file_type = FileType::from(file_name);
+            let first_line = self.rows.first().map(|row| row.string.as_str());
+            let file_type = FileType::detect(file_name, first_line);
+            if file_type.name() != self.file_type.name() {
+                #[cfg(feature = "tree-sitter")]
+                {
+                    self.syntax_tree = SyntaxTree::for_file_type(&file_type.name());
+                }
+                self.unhighlight_rows(0, self.rows.len());
+            }
+            self.file_type = file_type;
             for row in &mut self.rows {
                 file.write_all(row.as_bytes())?;
                 file.write_all(b"\n")?;
@@ -120,7 +370,7 @@ impl Document {
         if at.y >= self.rows.len() {
             return None;
         }
-        let mut position = Position { x: at.x, x_word_index: at.x_word_index, y: at.y };
+        let mut position = at.clone();
 
         let start = if direction == SearchDirection::Forward {
             at.y
@@ -134,16 +384,17 @@ impl Document {
         };
         for _ in start..end {
             if let Some(row) = self.rows.get(position.y) {
-                if let Some(x) = row.find(&query, position.x, direction) {
-                    position.x = x;
+                if let Some(x_word_index) = row.find(&query, position.x_word_index, direction) {
+                    position.x_word_index = x_word_index;
+                    position.x = row.column_of(x_word_index);
                     return Some(position);
                 }
                 if direction == SearchDirection::Forward {
                     position.y = position.y.saturating_add(1);
-                    position.x = 0;
+                    position.x_word_index = 0;
                 } else {
                     position.y = position.y.saturating_sub(1);
-                    position.x = self.rows[position.y].len();
+                    position.x_word_index = self.rows[position.y].len();
                 }
             } else {
                 return None;
@@ -151,24 +402,269 @@ impl Document {
         }
         None
     }
+    /// Highlights the rows up to and including `until`, or all rows.
     pub fn highlight(&mut self, word: &Option<String>, until: Option<usize>) {
-        let mut start_with_comment = false;
-        let until = if let Some(until) = until {
-            if until.saturating_add(1) < self.rows.len() {
-                until.saturating_add(1)
-            } else {
-                self.rows.len()
-            }
+        let end = until.map_or(self.rows.len(), |until| {
+            until.saturating_add(1).min(self.rows.len())
+        });
+        self.highlight_rows(word, end);
+    }
+    /// Highlights up to `count` more of the rows that are not known to be
+    /// up to date, for use while waiting for input.
+    pub fn highlight_more(&mut self, count: usize) {
+        let end = self.highlighted_until.saturating_add(count).min(self.rows.len());
+        self.highlight_rows(&None, end);
+    }
+    /// Whether every row is highlighted from the right start state.
+    pub fn is_highlighted(&self) -> bool {
+        self.highlighted_until >= self.rows.len()
+    }
+    /// Highlights the rows before `end`. Rows before `highlighted_until` are
+    /// up to date, unless a word is highlighted, which any row may contain.
+    fn highlight_rows(&mut self, word: &Option<String>, end: usize) {
+        #[cfg(feature = "tree-sitter")]
+        self.parse();
+        let start = if word.is_some() {
+            0
         } else {
-            self.rows.len()
+            self.highlighted_until.min(end)
         };
+        #[cfg(feature = "tree-sitter")]
+        if self.syntax_tree.is_some() {
+            self.highlight_tree(word, start, end);
+            self.highlighted_until = self.highlighted_until.max(end);
+            return;
+        }
+        let mut state = start
+            .checked_sub(1)
+            .and_then(|previous| self.rows.get(previous))
+            .map_or(highlighting::State::Normal, Row::end_state);
         #[allow(clippy::indexing_slicing)]
-        for row in &mut self.rows[..until] {
-            start_with_comment = row.highlight(
-                &self.file_type.highlighting_options(),
-                word,
-                start_with_comment,
-            );
+        for row in &mut self.rows[start..end] {
+            state = row.highlight(&self.file_type.highlighting_options(), word, state);
+        }
+        self.highlighted_until = self.highlighted_until.max(end);
+    }
+    /// Parses the document again if it changed. Rows whose syntax changed
+    /// along with the edited ones are highlighted again.
+    #[cfg(feature = "tree-sitter")]
+    fn parse(&mut self) {
+        if let (Some(syntax_tree), false) = (&mut self.syntax_tree, self.parsed) {
+            if let Some(first) = syntax_tree.parse(&self.rows) {
+                self.highlighted_until = self.highlighted_until.min(first);
+            }
+            self.parsed = true;
         }
     }
+    /// Highlights the rows `start..end` from the syntax tree. Any row after
+    /// a change may look different, so every row in the range is
+    /// highlighted again.
+    #[cfg(feature = "tree-sitter")]
+    fn highlight_tree(&mut self, word: &Option<String>, start: usize, end: usize) {
+        let syntax_tree = match &self.syntax_tree {
+            Some(syntax_tree) => syntax_tree,
+            None => return,
+        };
+        if start >= end {
+            return;
+        }
+        let mut byte: usize = self
+            .rows
+            .iter()
+            .take(start)
+            .map(|row| row.string.len().saturating_add(1))
+            .sum();
+        #[allow(clippy::indexing_slicing)]
+        for row in &mut self.rows[start..end] {
+            let row_end = byte.saturating_add(row.string.len());
+            let types = syntax_tree.highlight(byte..row_end);
+            let highlighting = row
+                .string
+                .grapheme_indices(true)
+                .map(|(index, _)| types.get(index).copied().unwrap_or(highlighting::Type::None))
+                .collect();
+            row.set_highlighting(highlighting, word);
+            byte = row_end.saturating_add(1);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test_super {
+    use super::*;
+    use crate::Whitespace;
+
+    #[test]
+    fn test_insert_str() {
+        let mut doc = Document::default();
+        doc.rows.push(Row::from("fn main() {}"));
+        let mut at = Position { x: 11, x_word_index: 11, y: 0 };
+        doc.insert_str(&mut at, "\r\n    println!(\"中\");\r\n");
+        let rows: Vec<&str> = doc.rows.iter().map(|row| row.string.as_str()).collect();
+        assert_eq!(rows, vec!["fn main() {", "    println!(\"中\");", "}"]);
+        assert_eq!((at.x, at.x_word_index, at.y), (0, 0, 2));
+
+        doc.insert_str(&mut at, "中a");
+        assert_eq!(doc.rows[2].string, "中a}");
+        assert_eq!((at.x, at.x_word_index, at.y), (3, 2, 2));
+        assert!(doc.is_dirty());
+    }
+
+    #[test]
+    fn test_tab_width() {
+        let mut wide = Document::default();
+        let mut narrow = Document::default();
+        narrow.set_tab_width(2);
+        for doc in [&mut wide, &mut narrow] {
+            let mut at = Position::default();
+            doc.insert_str(&mut at, "\ta\n\tb");
+            doc.insert_newline(&at);
+        }
+        assert_eq!(wide.rows[1].width(), 5);
+        assert_eq!(narrow.rows[1].width(), 3);
+        assert_eq!(narrow.rows[2].tab_width(), 2);
+        wide.set_tab_width(8);
+        assert_eq!(wide.rows[0].width(), 9);
+    }
+
+    #[test]
+    fn test_ranges() {
+        let mut doc = Document::default();
+        for line in &["one 中文", "two", "three"] {
+            doc.rows.push(Row::from(*line));
+        }
+        let start = Position { x: 0, x_word_index: 4, y: 0 };
+        let end = Position { x: 0, x_word_index: 2, y: 2 };
+        assert_eq!(doc.text(&start, &end), "中文\ntwo\nth");
+        doc.delete_range(&start, &end);
+        let rows: Vec<&str> = doc.rows.iter().map(|row| row.string.as_str()).collect();
+        assert_eq!(rows, vec!["one ree"]);
+
+        doc.rows.push(Row::from("four"));
+        assert_eq!(doc.delete_rows(0, 0), "one ree");
+        assert_eq!(doc.rows.len(), 1);
+        assert_eq!(doc.rows[0].string, "four");
+    }
+
+    #[test]
+    fn test_highlight_multiline_strings() {
+        use highlighting::Type;
+        let mut doc = Document::default();
+        doc.file_type = FileType::detect("script.py", None);
+        for line in &["x = '''doc", "# not a comment", "end''' # comment"] {
+            doc.rows.push(Row::from(*line));
+        }
+        doc.highlight(&None, None);
+        let types = |row: &Row| -> Vec<Type> {
+            row.render(0, 80, Whitespace::default()).into_iter().map(|(_, hl_type)| hl_type).collect()
+        };
+        assert_eq!(types(&doc.rows[0]), vec![Type::None, Type::String]);
+        assert_eq!(types(&doc.rows[1]), vec![Type::String]);
+        assert_eq!(types(&doc.rows[2]), vec![Type::String, Type::None, Type::Comment]);
+    }
+
+    #[test]
+    fn test_highlight_in_steps() {
+        use highlighting::Type;
+        let mut doc = Document::default();
+        doc.file_type = FileType::detect("main.rs", None);
+        for _ in 0..3000 {
+            doc.rows.push(Row::from("x"));
+        }
+        doc.highlight(&None, Some(9));
+        assert_eq!(doc.highlighted_until, 10);
+        let first = |doc: &Document, index: usize| doc.rows[index].render(0, 1, Whitespace::default())[0].1;
+
+        // Opening a comment only highlights what is on screen right away.
+        doc.insert_str(&mut Position::default(), "/* ");
+        assert_eq!(doc.highlighted_until, 0);
+        doc.highlight(&None, Some(9));
+        assert_eq!(first(&doc, 9), Type::MultilineComment);
+        assert_eq!(first(&doc, 2999), Type::None);
+        assert!(!doc.is_highlighted());
+        doc.highlight_more(1000);
+        assert_eq!(doc.highlighted_until, 1010);
+        doc.highlight_more(5000);
+        assert!(doc.is_highlighted());
+        assert_eq!(first(&doc, 2999), Type::MultilineComment);
+
+        // Rows after a change that leaves the state alone stay as they are.
+        doc.replace_row(5, "y");
+        assert_eq!(doc.highlighted_until, 5);
+        doc.highlight(&None, None);
+        assert!(doc.rows[6].is_highlighted);
+        assert_eq!(first(&doc, 5), Type::MultilineComment);
+    }
+
+    #[cfg(feature = "tree-sitter")]
+    #[test]
+    fn test_highlight_tree() {
+        use highlighting::Type;
+        let mut doc = Document::default();
+        doc.syntax_tree = SyntaxTree::for_file_type("Rust");
+        for line in &["/* a", "b */ fn f() {}"] {
+            doc.rows.push(Row::from(*line));
+        }
+        doc.highlight(&None, None);
+        let types = |row: &Row| -> Vec<Type> {
+            row.render(0, 80, Whitespace::default()).into_iter().map(|(_, hl_type)| hl_type).collect()
+        };
+        assert_eq!(types(&doc.rows[0]), vec![Type::MultilineComment]);
+        let rendered = types(&doc.rows[1]);
+        assert_eq!(rendered[..4], [Type::MultilineComment, Type::None, Type::PrimaryKeywords, Type::None]);
+        assert_eq!(rendered[4], Type::Function);
+
+        doc.delete_rows(0, 0);
+        doc.highlight(&None, None);
+        assert!(!types(&doc.rows[0]).contains(&Type::MultilineComment));
+    }
+
+    #[cfg(feature = "tree-sitter")]
+    #[test]
+    fn test_tree_edits() {
+        let mut doc = Document::default();
+        doc.syntax_tree = SyntaxTree::for_file_type("Rust");
+        // Highlights `doc` after parsing it incrementally and from scratch.
+        let check = |doc: &mut Document| {
+            doc.highlight(&None, None);
+            let mut fresh = Document::default();
+            fresh.syntax_tree = SyntaxTree::for_file_type("Rust");
+            for row in &doc.rows {
+                fresh.rows.push(Row::from(row.string.as_str()));
+            }
+            fresh.highlight(&None, None);
+            for (row, fresh_row) in doc.rows.iter().zip(&fresh.rows) {
+                assert_eq!(
+                    row.render(0, 80, Whitespace::default()),
+                    fresh_row.render(0, 80, Whitespace::default())
+                );
+            }
+        };
+        let mut at = Position::default();
+        doc.insert_str(&mut at, "fn f() {\n    let x = 1;\n}");
+        check(&mut doc);
+        doc.insert(&mut Position { x: 0, x_word_index: 0, y: 3 }, '/');
+        check(&mut doc);
+        doc.insert_newline(&Position { x: 8, x_word_index: 8, y: 0 });
+        check(&mut doc);
+        doc.insert_str(&mut Position { x: 0, x_word_index: 0, y: 1 }, "/* a\nb */");
+        check(&mut doc);
+        doc.delete(&Position { x: 3, x_word_index: 3, y: 1 });
+        check(&mut doc);
+        doc.delete(&Position { x: 0, x_word_index: 0, y: 0 });
+        check(&mut doc);
+        doc.replace_row(2, "    \"s\";");
+        check(&mut doc);
+        doc.delete_range(&Position { x: 2, x_word_index: 2, y: 0 }, &Position { x: 1, x_word_index: 1, y: 2 });
+        check(&mut doc);
+        doc.delete_rows(1, 1);
+        check(&mut doc);
+        doc.delete_rows(1, 5);
+        check(&mut doc);
+        doc.insert_newline(&Position { x: 0, x_word_index: 0, y: 1 });
+        check(&mut doc);
+        doc.delete_rows(0, 1);
+        assert!(doc.rows.is_empty());
+        check(&mut doc);
+    }
 }
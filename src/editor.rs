@@ -1,24 +1,42 @@
+use crate::config::config_dir;
+use crate::ex::{self, ExCommand};
+use crate::keymap::{format_chord, Command, Key, Keymap, Lookup};
+use crate::macros::{self, Macros, MACROS_FILE_NAME};
+use crate::palette::Palette;
+use crate::prompt::{History, Input};
+use crate::vim::{Action, Insert, Mode, Motion, Operator, Register, Vim};
+use crate::ColorSupport;
+use crate::Config;
 use crate::Document;
+use crate::FileType;
 use crate::Row;
 use crate::Terminal;
+use crate::Theme;
+use crate::Whitespace;
+use std::cmp;
+use std::collections::{HashMap, VecDeque};
 use std::env;
+use std::io::ErrorKind;
+use std::mem;
 use std::time::Duration;
 use std::time::Instant;
-use crossterm::style::Color;
-use crossterm::event::{self, KeyCode, KeyEvent, KeyModifiers};
-use crossterm::terminal::disable_raw_mode;
-
-
-const STATUS_FG_COLOR: Color = Color::Rgb { r: 63, g: 63, b: 63 };
-const STATUS_BG_COLOR: Color = Color::Rgb {
-    r: 135,
-    g: 206,
-    b: 235,
-};
+use crossterm::event::{Event, KeyCode, KeyEvent, KeyModifiers};
+use regex::RegexBuilder;
+use unicode_segmentation::UnicodeSegmentation;
+use unicode_width::UnicodeWidthStr;
 
 
 const VERSION: &str = env!("CARGO_PKG_VERSION");
-const QUIT_TIMES: u8 = 3;
+/// How long `run` waits for input before doing idle work such as autosave.
+const IDLE_TICK: Duration = Duration::from_millis(250);
+/// How many rows past the screen `idle` highlights at a time. While any are
+/// left, `run` only waits briefly for input.
+const IDLE_HIGHLIGHT_ROWS: usize = 1000;
+/// Most entries the command palette shows at once.
+const PALETTE_HEIGHT: usize = 8;
+/// How deeply macros may play other macros, which stops a macro that
+/// plays itself.
+const MAX_MACRO_DEPTH: usize = 100;
 
 #[derive(PartialEq, Copy, Clone)]
 pub enum SearchDirection {
@@ -56,6 +74,29 @@ pub struct Editor {
     status_message: StatusMessage,
     quit_times: u8,
     highlighted_word: Option<String>,
+    theme: Theme,
+    config: Config,
+    last_input: Instant,
+    keymap: Keymap,
+    /// Keys of a multi-key chord typed so far.
+    pending_keys: Vec<Key>,
+    /// First line shown while the help screen is open.
+    help_offset: Option<usize>,
+    palette: Option<Palette>,
+    /// The modal keymap, when enabled in the config.
+    vim: Option<Vim>,
+    register: Option<Register>,
+    /// Where the visual mode selection started.
+    selection_anchor: Position,
+    /// Answers given to each kind of prompt, oldest first.
+    prompt_history: HashMap<History, Vec<String>>,
+    /// Where the cursor is in the message bar while a prompt is open.
+    prompt_cursor: Option<usize>,
+    macros: Macros,
+    /// Keys of a playing macro, read before the terminal's events.
+    replay: VecDeque<Key>,
+    /// How many macros are playing, counting macros played by macros.
+    replay_depth: usize,
 }
 
 impl Editor {
@@ -67,98 +108,539 @@ impl Editor {
             if self.should_quit {
                 break;
             }
-            if let Err(error) = self.process_keypress() {
-                die(error);
+            let timeout = if self.document.is_highlighted() {
+                IDLE_TICK
+            } else {
+                Duration::ZERO
+            };
+            match self.terminal.poll_event(timeout) {
+                Ok(true) => {
+                    if let Err(error) = self.process_keypress() {
+                        die(error);
+                    }
+                }
+                Ok(false) => self.idle(),
+                Err(error) => die(error),
             }
         }
     }
-    pub fn default() -> Self {
+    pub fn default(config: Config, config_errors: &[String]) -> Self {
         let args: Vec<String> = env::args().collect();
-        let mut initial_status =
-            String::from("HELP: Ctrl-F = find | Ctrl-S = save | Ctrl-Q = quit");
-
-        let document = if let Some(file_name) = args.get(1) {
-            let doc = Document::open(file_name);
-            if let Ok(doc) = doc {
-                doc
+        let terminal = Terminal::default().expect("Failed to initialize terminal");
+        let mut editor = if let Some(file_name) = args.get(1) {
+            if let Ok(document) = Document::open(file_name) {
+                Self::new(terminal, document, config)
             } else {
-                initial_status = format!("ERR: Could not open file: {}", file_name);
-                Document::default()
+                let mut editor = Self::new(terminal, Document::default(), config);
+                editor.status_message =
+                    StatusMessage::from(format!("ERR: Could not open file: {}", file_name));
+                editor
             }
         } else {
-            Document::default()
+            Self::new(terminal, Document::default(), config)
         };
-
+        let mut errors = config_errors.to_vec();
+        errors.extend(FileType::load_errors().iter().cloned());
+        if let Some(dir) = config_dir() {
+            let (macros, macro_errors) = Macros::load(dir.join(MACROS_FILE_NAME));
+            editor.macros = macros;
+            errors.extend(macro_errors);
+        }
+        if !errors.is_empty() {
+            editor.status_message = StatusMessage::from(format!("ERR: {}", errors.join("; ")));
+        }
+        editor
+    }
+    pub fn new(mut terminal: Terminal, mut document: Document, config: Config) -> Self {
+        let (keymap, keymap_errors) = Keymap::from_config(&config.keybindings);
+        let help: Vec<String> = [Command::Palette, Command::Help, Command::Quit]
+            .iter()
+            .filter_map(|command| {
+                keymap
+                    .chord_for(*command)
+                    .map(|chord| format!("{} = {}", chord, command.name()))
+            })
+            .collect();
+        let mut status_message = if keymap_errors.is_empty() {
+            StatusMessage::from(format!("HELP: {}", help.join(" | ")))
+        } else {
+            StatusMessage::from(format!("ERR: {}", keymap_errors.join("; ")))
+        };
+        let theme = Theme::load(&config.theme).unwrap_or_else(|error| {
+            status_message = StatusMessage::from(format!(
+                "ERR: Could not load theme {}: {}",
+                config.theme, error
+            ));
+            Theme::default()
+        });
+        if let Some(colors) = config.colors {
+            terminal.set_color_support(colors);
+        }
+        document.set_tab_width(config.tab_width);
+        let vim = if config.vim { Some(Vim::default()) } else { None };
         Self {
             should_quit: false,
-            terminal: Terminal::default().expect("Failed to initialize terminal"),
+            terminal,
             document,
             cursor_position: Position::default(),
             offset: Position::default(),
-            status_message: StatusMessage::from(initial_status),
-            quit_times: QUIT_TIMES,
+            status_message,
+            quit_times: config.quit_times,
             highlighted_word: None,
+            theme,
+            config,
+            last_input: Instant::now(),
+            keymap,
+            pending_keys: Vec::new(),
+            help_offset: None,
+            palette: None,
+            vim,
+            register: None,
+            selection_anchor: Position::default(),
+            prompt_history: HashMap::new(),
+            prompt_cursor: None,
+            macros: Macros::default(),
+            replay: VecDeque::new(),
+            replay_depth: 0,
+        }
+    }
+    pub fn should_quit(&self) -> bool {
+        self.should_quit
+    }
+    pub fn document(&self) -> &Document {
+        &self.document
+    }
+    pub fn cursor_position(&self) -> &Position {
+        &self.cursor_position
+    }
+    pub fn theme(&self) -> &Theme {
+        &self.theme
+    }
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+    pub fn keymap(&self) -> &Keymap {
+        &self.keymap
+    }
+    /// The Vim mode, if the Vim keymap is enabled.
+    pub fn vim_mode(&self) -> Option<Mode> {
+        self.vim.as_ref().map(Vim::mode)
+    }
+    /// Work done while waiting for input.
+    pub fn idle(&mut self) {
+        self.document.highlight_more(IDLE_HIGHLIGHT_ROWS);
+        let autosave = Duration::from_secs(self.config.autosave);
+        if self.config.autosave > 0
+            && self.document.is_dirty()
+            && self.document.file_name.is_some()
+            && self.last_input.elapsed() >= autosave
+        {
+            if self.document.save().is_ok() {
+                self.status_message = StatusMessage::from("Autosaved.".to_string());
+            } else {
+                self.status_message = StatusMessage::from("Error writing file!".to_string());
+            }
         }
     }
 
-    fn refresh_screen(&mut self) -> Result<(), std::io::Error> {
-        Terminal::cursor_hide();
-        Terminal::cursor_position(&Position::default());
+    pub fn refresh_screen(&mut self) -> Result<(), std::io::Error> {
         if self.should_quit {
-            Terminal::clear_screen();
-            println!("Goodbye.\r");
+            return Ok(());
+        }
+        self.terminal.fill(self.theme.text());
+        self.document.highlight(
+            &self.highlighted_word,
+            Some(
+                self.offset
+                    .y
+                    .saturating_add(self.terminal.size().height as usize),
+            ),
+        );
+        let mut position = if self.help_offset.is_some() {
+            self.draw_help();
+            Position::default()
         } else {
-            self.document.highlight(
-                &self.highlighted_word,
-                Some(
-                    self.offset
-                        .y
-                        .saturating_add(self.terminal.size().height as usize),
-                ),
-            );
             self.draw_rows();
-            self.draw_status_bar();
-            self.draw_message_bar();
-            Terminal::cursor_position(&Position {
-                x: self.cursor_position.x.saturating_sub(self.offset.x),
-                x_word_index: 0,
-                y: self.cursor_position.y.saturating_sub(self.offset.y),
-            });
+            self.draw_palette();
+            self.screen_cursor_position()
+        };
+        self.draw_status_bar();
+        if let Some(prompt_position) = self.draw_message_bar() {
+            position = prompt_position;
         }
-        Terminal::cursor_show();
-        Terminal::flush()
+        self.terminal.cursor_position(&position);
+        self.terminal.flush()
     }
     fn save(&mut self) {
         if self.document.file_name.is_none() {
-            let new_name = self.prompt("Save as: ", |_, _, _| {}).unwrap_or(None);
-            if new_name.is_none() {
-                self.status_message = StatusMessage::from("Save aborted.".to_string());
-                return;
-            }
-            self.document.file_name = new_name;
+            self.save_as();
+            return;
         }
-
+        self.write_file();
+    }
+    fn save_as(&mut self) {
+        let new_name = self.prompt("Save as: ", Some(History::SaveAs), |_, _, _| {}).unwrap_or(None);
+        if new_name.is_none() {
+            self.status_message = StatusMessage::from("Save aborted.".to_string());
+            return;
+        }
+        self.document.file_name = new_name;
+        self.write_file();
+    }
+    fn write_file(&mut self) {
         if self.document.save().is_ok() {
             self.status_message = StatusMessage::from("File saved successfully.".to_string());
         } else {
             self.status_message = StatusMessage::from("Error writing file!".to_string());
         }
     }
+    fn switch_theme(&mut self) {
+        let prompt = format!("Theme ({}, or a file): ", Theme::builtin_names().join(", "));
+        let name = self.prompt(&prompt, None, |_, _, _| {}).unwrap_or(None);
+        if let Some(name) = name {
+            match Theme::load(&name) {
+                Ok(theme) => {
+                    self.status_message =
+                        StatusMessage::from(format!("Switched to theme {}.", theme.name()));
+                    self.theme = theme;
+                }
+                Err(error) => {
+                    self.status_message =
+                        StatusMessage::from(format!("ERR: Could not load theme {}: {}", name, error));
+                }
+            }
+        }
+    }
+    fn command_palette(&mut self) {
+        self.palette = Some(Palette::default());
+        let accepted = self
+            .prompt_input("Command: ", None, |editor, key, query| {
+                if let Some(palette) = &mut editor.palette {
+                    match key.code() {
+                        KeyCode::Up => palette.select_previous(),
+                        KeyCode::Down => palette.select_next(),
+                        _ => palette.filter(query),
+                    }
+                }
+            })
+            .unwrap_or(None)
+            .is_some();
+        let selected = self.palette.take().and_then(|palette| palette.selected());
+        if let (true, Some(command)) = (accepted, selected) {
+            self.execute(command);
+        }
+    }
+    fn command_line(&mut self) {
+        let mut completions: Vec<String> = Vec::new();
+        let mut completion_index: usize = 0;
+        let line = self
+            .prompt(":", Some(History::CommandLine), |_, key, input| match key.code() {
+                KeyCode::Tab => {
+                    if completions.is_empty() {
+                        completions = ex::complete(input);
+                        completion_index = 0;
+                    } else {
+                        completion_index = completion_index.saturating_add(1) % completions.len();
+                    }
+                    if let Some(completion) = completions.get(completion_index) {
+                        *input = completion.clone();
+                    }
+                }
+                _ => completions.clear(),
+            })
+            .unwrap_or(None);
+        if let Some(line) = line {
+            self.execute_ex(&line);
+        }
+    }
+    fn execute_ex(&mut self, line: &str) {
+        let last = self.document.len().saturating_sub(1);
+        let command = match ex::parse(line, self.cursor_position.y, last) {
+            Ok(command) => command,
+            Err(error) => {
+                self.status_message = StatusMessage::from(format!("ERR: {}", error));
+                return;
+            }
+        };
+        let unsaved = "ERR: No write since last change (add ! to override)";
+        match command {
+            ExCommand::Write { file, quit } => {
+                if file.is_some() {
+                    self.document.file_name = file;
+                }
+                if self.document.file_name.is_none() {
+                    self.status_message = StatusMessage::from("ERR: No file name".to_string());
+                    return;
+                }
+                self.write_file();
+                if quit && !self.document.is_dirty() {
+                    self.should_quit = true;
+                }
+            }
+            ExCommand::Quit { force } => {
+                if force || !self.document.is_dirty() {
+                    self.should_quit = true;
+                } else {
+                    self.status_message = StatusMessage::from(unsaved.to_string());
+                }
+            }
+            ExCommand::Edit { file, force } => {
+                if self.document.is_dirty() && !force {
+                    self.status_message = StatusMessage::from(unsaved.to_string());
+                    return;
+                }
+                self.open(&file);
+            }
+            ExCommand::Set(options) => {
+                for (name, value) in options {
+                    let previous = self.config.clone();
+                    if let Err(error) = self.config.set(&name, value.as_deref()) {
+                        self.status_message = StatusMessage::from(format!("ERR: {}", error));
+                        return;
+                    }
+                    self.apply_config(&previous);
+                }
+            }
+            ExCommand::Goto(line) => self.set_cursor(self.first_non_blank(line), line),
+            ExCommand::Substitute {
+                first,
+                last,
+                pattern,
+                replacement,
+                global,
+                ignore_case,
+            } => {
+                let regex = match RegexBuilder::new(&pattern).case_insensitive(ignore_case).build() {
+                    Ok(regex) => regex,
+                    Err(error) => {
+                        self.status_message = StatusMessage::from(format!("ERR: {}", error));
+                        return;
+                    }
+                };
+                let mut substitutions: usize = 0;
+                let mut changed_lines: usize = 0;
+                let mut last_changed = None;
+                for y in first..=last {
+                    let text = match self.document.row(y) {
+                        Some(row) => row.string.clone(),
+                        None => break,
+                    };
+                    let (count, replaced) = if global {
+                        (regex.find_iter(&text).count(), regex.replace_all(&text, replacement.as_str()))
+                    } else {
+                        (usize::from(regex.is_match(&text)), regex.replace(&text, replacement.as_str()))
+                    };
+                    if count == 0 {
+                        continue;
+                    }
+                    self.document.replace_row(y, &replaced);
+                    substitutions = substitutions.saturating_add(count);
+                    changed_lines = changed_lines.saturating_add(1);
+                    last_changed = Some(y);
+                }
+                if let Some(y) = last_changed {
+                    self.set_cursor(self.first_non_blank(y), y);
+                    self.status_message = StatusMessage::from(format!(
+                        "{} substitutions on {} lines",
+                        substitutions, changed_lines
+                    ));
+                } else {
+                    self.status_message =
+                        StatusMessage::from(format!("ERR: Pattern not found: {}", pattern));
+                }
+            }
+        }
+    }
+    /// Replaces the document with `file_name`, or with a new, empty
+    /// document by that name if the file does not exist.
+    fn open(&mut self, file_name: &str) {
+        match Document::open(file_name) {
+            Ok(document) => self.document = document,
+            Err(error) if error.kind() == ErrorKind::NotFound => {
+                self.document = Document::default();
+                self.document.file_name = Some(file_name.to_string());
+                self.status_message = StatusMessage::from(format!("\"{}\" [New]", file_name));
+            }
+            Err(error) => {
+                self.status_message =
+                    StatusMessage::from(format!("ERR: Could not open file: {}", error));
+                return;
+            }
+        }
+        self.document.set_tab_width(self.config.tab_width);
+        self.cursor_position = Position::default();
+        self.offset = Position::default();
+    }
+    /// Brings the editor in line with settings changed since `previous`.
+    fn apply_config(&mut self, previous: &Config) {
+        if self.config.tab_width != previous.tab_width {
+            self.document.set_tab_width(self.config.tab_width);
+            let Position { x_word_index, y, .. } = self.cursor_position;
+            self.set_cursor(x_word_index, y);
+        }
+        if self.config.theme != previous.theme {
+            match Theme::load(&self.config.theme) {
+                Ok(theme) => self.theme = theme,
+                Err(error) => {
+                    self.status_message = StatusMessage::from(format!(
+                        "ERR: Could not load theme {}: {}",
+                        self.config.theme, error
+                    ));
+                    self.config.theme = previous.theme.clone();
+                }
+            }
+        }
+        if self.config.colors != previous.colors {
+            let colors = self.config.colors.unwrap_or_else(ColorSupport::detect);
+            self.terminal.set_color_support(colors);
+        }
+        if self.config.vim != previous.vim {
+            self.vim = if self.config.vim { Some(Vim::default()) } else { None };
+            if let Some(vim) = &mut self.vim {
+                vim.set_recording(self.macros.recording().is_some());
+            }
+        }
+        if self.config.quit_times != previous.quit_times {
+            self.quit_times = self.config.quit_times;
+        }
+    }
+    /// Reads the next event: a key of the playing macro if there is one,
+    /// otherwise an event from the terminal, which is recorded while a
+    /// macro is being recorded. Pasted text is not recorded.
+    fn read_event(&mut self) -> Result<Event, std::io::Error> {
+        if let Some(key) = self.replay.pop_front() {
+            return Ok(Event::Key(KeyEvent::new(key.code(), key.modifiers())));
+        }
+        let event = self.terminal.read_event()?;
+        if let Event::Key(keyevent) = event {
+            self.macros.record(Key::from(keyevent));
+        }
+        Ok(event)
+    }
+    fn record_macro(&mut self) {
+        let register = self
+            .prompt("Record macro into register (a-z, 0-9): ", None, |_, _, _| {})
+            .unwrap_or(None);
+        if let Some(register) = register {
+            let mut chars = register.chars();
+            match (chars.next(), chars.next()) {
+                (Some(c), None) if macros::is_register(c) => self.start_recording(c),
+                _ => {
+                    self.status_message =
+                        StatusMessage::from(format!("ERR: Invalid register: {}", register));
+                }
+            }
+        }
+    }
+    fn start_recording(&mut self, register: char) {
+        if !macros::is_register(register) {
+            self.status_message =
+                StatusMessage::from(format!("ERR: Invalid register: {}", register));
+            return;
+        }
+        self.macros.start_recording(register);
+        if let Some(vim) = &mut self.vim {
+            vim.set_recording(true);
+        }
+        self.status_message = StatusMessage::from(format!("Recording macro @{}.", register));
+    }
+    fn stop_recording(&mut self) {
+        if let Some(vim) = &mut self.vim {
+            vim.set_recording(false);
+        }
+        self.status_message = match self.macros.stop_recording() {
+            Ok(Some(register)) => StatusMessage::from(format!("Recorded macro @{}.", register)),
+            Ok(None) => StatusMessage::from(String::new()),
+            Err(error) => StatusMessage::from(format!("ERR: Could not save macros: {}", error)),
+        };
+    }
+    fn play_macro_prompt(&mut self) {
+        let answer = self
+            .prompt("Play macro (register, after an optional count): ", None, |_, _, _| {})
+            .unwrap_or(None);
+        if let Some(answer) = answer {
+            let answer = answer.trim();
+            let digits = answer
+                .find(|c: char| !c.is_ascii_digit())
+                .unwrap_or_else(|| answer.len());
+            let (count, register) = answer.split_at(digits);
+            let count = count.parse().unwrap_or(1);
+            let mut chars = register.chars();
+            match (chars.next(), chars.next()) {
+                (Some(c), None) => self.play_macro(c, count),
+                _ => {
+                    self.status_message =
+                        StatusMessage::from(format!("ERR: Invalid register: {}", register));
+                }
+            }
+        }
+    }
+    /// Plays the macro in `register` `count` times, before any keys left of
+    /// a macro that is already playing.
+    fn play_macro(&mut self, register: char, count: usize) {
+        let keys = match self.macros.play(register) {
+            Some(keys) => keys,
+            None => {
+                self.status_message =
+                    StatusMessage::from(format!("ERR: No macro in register {}", register));
+                return;
+            }
+        };
+        if self.replay_depth >= MAX_MACRO_DEPTH {
+            self.replay.clear();
+            self.status_message = StatusMessage::from("ERR: Macros nest too deeply".to_string());
+            return;
+        }
+        let rest = mem::take(&mut self.replay);
+        for _ in 0..count {
+            self.replay.extend(keys.iter().copied());
+        }
+        self.replay.extend(rest);
+        self.replay_depth = self.replay_depth.saturating_add(1);
+        while !self.replay.is_empty() && !self.should_quit {
+            if let Err(error) = self.process_keypress() {
+                self.replay.clear();
+                self.status_message = StatusMessage::from(format!("ERR: {}", error));
+            }
+        }
+        self.replay_depth = self.replay_depth.saturating_sub(1);
+    }
+    fn goto_line(&mut self) {
+        let line = self.prompt("Go to line: ", Some(History::GotoLine), |_, _, _| {}).unwrap_or(None);
+        if let Some(line) = line {
+            match line.trim().parse::<usize>() {
+                Ok(number) if number >= 1 && number <= self.document.len() => {
+                    self.cursor_position = Position {
+                        x: 0,
+                        x_word_index: 0,
+                        y: number.saturating_sub(1),
+                    };
+                }
+                _ => {
+                    self.status_message =
+                        StatusMessage::from(format!("ERR: No line {} in this file.", line));
+                }
+            }
+        }
+    }
     fn search(&mut self) {
         let old_position = self.cursor_position.clone();
         let mut direction = SearchDirection::Forward;
         let query = self
             .prompt(
-                "Search (ESC to cancel, Arrows to navigate): ",
+                "Search (ESC to cancel, Ctrl-N/Ctrl-P to navigate): ",
+                Some(History::Search),
                 |editor, key, query| {
                     let mut moved = false;
-                    match key {
-                        KeyCode::Right | KeyCode::Down => {
+                    match (key.code(), key.modifiers()) {
+                        (KeyCode::Char('n'), KeyModifiers::CONTROL) => {
                             direction = SearchDirection::Forward;
                             editor.move_cursor(KeyCode::Right);
                             moved = true;
                         }
-                        KeyCode::Left | KeyCode::Up => direction = SearchDirection::Backward,
+                        (KeyCode::Char('p'), KeyModifiers::CONTROL) => {
+                            direction = SearchDirection::Backward;
+                        }
                         _ => direction = SearchDirection::Forward,
                     }
                     if let Some(position) =
@@ -182,95 +664,232 @@ impl Editor {
         }
         self.highlighted_word = None;
     }
-    fn process_keypress(&mut self) -> Result<(), std::io::Error> {
-        let pressed_key = Terminal::read_key()?;
-        match pressed_key {
-            KeyEvent {
-                code: KeyCode::Char('q'), modifiers: KeyModifiers::CONTROL
-            } => {
-                if self.quit_times > 0 && self.document.is_dirty() {
-                    self.status_message = StatusMessage::from(format!(
-                        "WARNING! File has unsaved changes. Press Ctrl-Q {} more times to quit.",
-                        self.quit_times
-                    ));
-                    self.quit_times -= 1;
-                    return Ok(());
-                }
-                disable_raw_mode();
-                self.should_quit = true
-            }
-            // Key::Ctrl('s') => self.save(),
-            KeyEvent {
-                code: KeyCode::Char('s'), modifiers: KeyModifiers::CONTROL
-            } => self.save(),
-            // Key::Ctrl('f') => self.search(),
-            KeyEvent {
-                code: KeyCode::Char('f'), modifiers: KeyModifiers::CONTROL
-            } => self.search(),
-            KeyEvent {
-                code: KeyCode::Char(c), ..
-            } => {
-                self.document.insert(&mut self.cursor_position, c);
-                self.move_cursor(KeyCode::Right);
+    pub fn process_keypress(&mut self) -> Result<(), std::io::Error> {
+        if self.pending_keys.is_empty() && !self.vim.as_ref().map_or(false, Vim::is_pending) {
+            self.macros.start_chord();
+        }
+        let event = self.read_event()?;
+        self.last_input = Instant::now();
+        match event {
+            Event::Key(pressed_key) => self.process_key(Key::from(pressed_key)),
+            Event::Paste(text) => {
+                self.document.insert_str(&mut self.cursor_position, &text);
+                self.scroll();
+                self.reset_quit_times();
             }
-            KeyEvent {
-                code: KeyCode::Enter, ..
-            } => {
-                self.document.insert_newline(&self.cursor_position);
-                self.cursor_position.y = self.cursor_position.y.saturating_add(1);
-                self.cursor_position.x = 0;
-                self.cursor_position.x_word_index = 0;
+            Event::Resize(width, height) => {
+                self.terminal.resize(width, height);
+                self.scroll();
             }
-            KeyEvent {
-                code: KeyCode::Delete, ..
-            } => self.document.delete(&self.cursor_position),
-
-            KeyEvent {
-                code: KeyCode::Backspace, ..
-            } => {
-                if self.cursor_position.x_word_index > 0 || self.cursor_position.y > 0 {
-                    self.move_cursor(KeyCode::Left);
-                    self.document.delete(&self.cursor_position);
+            _ => (),
+        }
+        Ok(())
+    }
+    fn process_key(&mut self, key: Key) {
+        if self.help_offset.is_some() {
+            self.scroll_help(key);
+            return;
+        }
+        if self.pending_keys.is_empty() {
+            if let Some(vim) = &mut self.vim {
+                match vim.feed(key) {
+                    Some(Action::Passthrough) => (),
+                    Some(action) => {
+                        self.vim_action(action);
+                        self.clamp_vim_cursor();
+                        self.scroll();
+                        self.reset_quit_times();
+                        return;
+                    }
+                    None => return,
                 }
             }
-            KeyEvent {
-                code: KeyCode::Up, ..
+        }
+        self.pending_keys.push(key);
+        match self.keymap.lookup(&self.pending_keys) {
+            Lookup::Prefix => {
+                self.status_message =
+                    StatusMessage::from(format!("{} -", format_chord(&self.pending_keys)));
+                return;
             }
-            | KeyEvent {
-                code: KeyCode::Down, ..
+            Lookup::Command(command) => {
+                if mem::take(&mut self.pending_keys).len() > 1 {
+                    self.status_message = StatusMessage::from(String::new());
+                }
+                let quit_times = self.quit_times;
+                self.execute(command);
+                if self.quit_times < quit_times {
+                    return;
+                }
             }
-            | KeyEvent {
-                code: KeyCode::Left, ..
+            Lookup::Unbound => match mem::take(&mut self.pending_keys)[..] {
+                [key] if key.is_text() => {
+                    if let KeyCode::Char(c) = key.code() {
+                        self.document.insert(&mut self.cursor_position, c);
+                        self.move_cursor(KeyCode::Right);
+                    }
+                }
+                ref keys => {
+                    self.status_message =
+                        StatusMessage::from(format!("{} is not bound.", format_chord(keys)));
+                }
+            },
+        }
+        self.clamp_vim_cursor();
+        self.scroll();
+        self.reset_quit_times();
+    }
+    fn quit(&mut self) {
+        if self.quit_times > 0 && self.document.is_dirty() {
+            let chord = self
+                .keymap
+                .chord_for(Command::Quit)
+                .unwrap_or_else(|| String::from("quit"));
+            self.status_message = StatusMessage::from(format!(
+                "WARNING! File has unsaved changes. Press {} {} more times to quit.",
+                chord, self.quit_times
+            ));
+            self.quit_times -= 1;
+            return;
+        }
+        self.should_quit = true
+    }
+    fn execute(&mut self, command: Command) {
+        match command {
+            Command::Quit => self.quit(),
+            Command::Save => self.save(),
+            Command::SaveAs => self.save_as(),
+            Command::Find => self.search(),
+            Command::GotoLine => self.goto_line(),
+            Command::SwitchTheme => self.switch_theme(),
+            Command::Help => self.help_offset = Some(0),
+            Command::Palette => self.command_palette(),
+            Command::CommandLine => self.command_line(),
+            Command::RecordMacro => {
+                if self.macros.recording().is_some() {
+                    self.stop_recording();
+                } else {
+                    self.record_macro();
+                }
             }
-            | KeyEvent {
-                code: KeyCode::Right, ..
+            Command::PlayMacro => self.play_macro_prompt(),
+            Command::ToggleWhitespace => {
+                self.config.visible_whitespace = !self.config.visible_whitespace;
             }
-            | KeyEvent {
-                code: KeyCode::PageUp, ..
+            Command::MoveUp => self.move_cursor(KeyCode::Up),
+            Command::MoveDown => self.move_cursor(KeyCode::Down),
+            Command::MoveLeft => self.move_cursor(KeyCode::Left),
+            Command::MoveRight => self.move_cursor(KeyCode::Right),
+            Command::MoveWordLeft => self.move_word(SearchDirection::Backward),
+            Command::MoveWordRight => self.move_word(SearchDirection::Forward),
+            Command::PageUp => self.move_cursor(KeyCode::PageUp),
+            Command::PageDown => self.move_cursor(KeyCode::PageDown),
+            Command::LineStart => self.move_cursor(KeyCode::Home),
+            Command::LineEnd => self.move_cursor(KeyCode::End),
+            Command::DocumentStart => self.cursor_position = Position::default(),
+            Command::DocumentEnd => {
+                let y = self.document.len().saturating_sub(1);
+                let x_word_index = self.document.row(y).map_or(0, Row::len);
+                self.cursor_position = Position {
+                    x: self.document.row(y).map_or(0, Row::width),
+                    x_word_index,
+                    y,
+                };
             }
-            | KeyEvent {
-                code: KeyCode::PageDown, ..
+            Command::InsertNewline => {
+                self.document.insert_newline(&self.cursor_position);
+                self.cursor_position.y = self.cursor_position.y.saturating_add(1);
+                self.cursor_position.x = 0;
+                self.cursor_position.x_word_index = 0;
             }
-            | KeyEvent {
-                code: KeyCode::End, ..
+            Command::InsertTab => {
+                self.document.insert(&mut self.cursor_position, '\t');
+                self.move_cursor(KeyCode::Right);
+            }
+            Command::DeleteForward => self.document.delete(&self.cursor_position),
+            Command::DeleteBackward => {
+                if self.cursor_position.x_word_index > 0 || self.cursor_position.y > 0 {
+                    self.move_cursor(KeyCode::Left);
+                    self.document.delete(&self.cursor_position);
+                }
             }
-            | KeyEvent {
-                code: KeyCode::Home, ..
-            } => self.move_cursor(pressed_key.code),
-            _ => (),
         }
-        self.scroll();
-        if self.quit_times < QUIT_TIMES {
-            self.quit_times = QUIT_TIMES;
+    }
+    fn reset_quit_times(&mut self) {
+        if self.quit_times < self.config.quit_times {
+            self.quit_times = self.config.quit_times;
             self.status_message = StatusMessage::from(String::new());
         }
-        Ok(())
+    }
+    /// Width of the line number gutter, including its trailing space.
+    fn gutter_width(&self) -> usize {
+        if self.config.line_numbers {
+            cmp::max(self.document.len(), 1).to_string().len().saturating_add(1)
+        } else {
+            0
+        }
+    }
+    fn text_width(&self) -> usize {
+        cmp::max((self.terminal.size().width as usize).saturating_sub(self.gutter_width()), 1)
+    }
+    /// Returns the screen line within row `y` and the display column at
+    /// which that line starts, for a cursor at column `x` of a soft-wrapped
+    /// row.
+    fn wrapped_line_of(&self, x: usize, y: usize) -> (usize, usize) {
+        let starts = self
+            .document
+            .row(y)
+            .map_or_else(|| vec![0], |row| row.wrap(self.text_width()));
+        let line = starts.iter().rposition(|start| *start <= x).unwrap_or(0);
+        let start = starts.get(line).copied().unwrap_or(0);
+        if x.saturating_sub(start) >= self.text_width() {
+            return (line.saturating_add(1), x);
+        }
+        (line, start)
+    }
+    fn wrapped_height(&self, y: usize) -> usize {
+        self.document
+            .row(y)
+            .map_or(1, |row| row.wrap(self.text_width()).len())
+    }
+    /// Where the terminal cursor goes for the current cursor position.
+    fn screen_cursor_position(&self) -> Position {
+        let Position { x, y, .. } = self.cursor_position;
+        let gutter_width = self.gutter_width();
+        if self.config.soft_wrap {
+            let (line, start) = self.wrapped_line_of(x, y);
+            let lines_above: usize = (self.offset.y..y).map(|index| self.wrapped_height(index)).sum();
+            return Position {
+                x: x.saturating_sub(start).saturating_add(gutter_width),
+                x_word_index: 0,
+                y: lines_above.saturating_add(line),
+            };
+        }
+        Position {
+            x: x.saturating_sub(self.offset.x).saturating_add(gutter_width),
+            x_word_index: 0,
+            y: y.saturating_sub(self.offset.y),
+        }
     }
     fn scroll(&mut self) {
-        let Position { x, x_word_index, y } = self.cursor_position;
-        let width = self.terminal.size().width as usize;
+        let Position { x, y, .. } = self.cursor_position;
+        let width = self.text_width();
         let height = self.terminal.size().height as usize;
-        let mut offset = &mut self.offset;
+        if self.config.soft_wrap {
+            self.offset.x = 0;
+            if y < self.offset.y {
+                self.offset.y = y;
+            }
+            let (line, _) = self.wrapped_line_of(x, y);
+            let mut lines_above: usize =
+                (self.offset.y..y).map(|index| self.wrapped_height(index)).sum();
+            while lines_above.saturating_add(line) >= height && self.offset.y < y {
+                lines_above = lines_above.saturating_sub(self.wrapped_height(self.offset.y));
+                self.offset.y = self.offset.y.saturating_add(1);
+            }
+            return;
+        }
+        let offset = &mut self.offset;
         if y < offset.y {
             offset.y = y;
         } else if y >= offset.y.saturating_add(height) {
@@ -284,154 +903,349 @@ impl Editor {
     }
     fn move_cursor(&mut self, key: KeyCode) {
         let terminal_height = self.terminal.size().height as usize;
-        let Position { mut y, mut x_word_index, mut x } = self.cursor_position;
+        let Position {
+            mut y,
+            mut x_word_index,
+            ..
+        } = self.cursor_position;
         let height = self.document.len();
-        let mut width = if let Some(row) = self.document.row(y) {
-            // row.len()
-            row.width()
-        } else {
-            0
-        };
+        let row_len = |y| self.document.row(y).map_or(0, Row::len);
         match key {
-            KeyCode::Up => {
-                y = y.saturating_sub(1);
-                //计算x值,更新word_index
-                // if y > 0 {
-                if x_word_index > self.document.rows[y]
-                    .word_width_index.len() {
-                    x_word_index = self.document.rows[y]
-                        .word_width_index.len();
-                }
-                let mut x_cursor = 0;
-                for word_len in self.document.rows[y]
-                    .word_width_index.clone().iter().take(x_word_index) {
-                    x_cursor = x_cursor + word_len;
-                }
-                x = x_cursor;
-                // }
-            }
+            KeyCode::Up => y = y.saturating_sub(1),
             KeyCode::Down => {
-                //计算x值,更新word_index
-                if y >= 0 && height > 0 && y < height - 1 {
+                if y.saturating_add(1) < height {
                     y = y.saturating_add(1);
-                    if y < self.document.rows.len() {
-                        if x_word_index > self.document.rows[y]
-                            .word_width_index.len() {
-                            x_word_index = self.document.rows[y]
-                                .word_width_index.len();
-                        }
-                        let mut x_cursor = 0;
-                        for word_len in self.document.rows[y]
-                            .word_width_index.clone().iter().take(x_word_index) {
-                            x_cursor = x_cursor + word_len;
-                        }
-                        x = x_cursor;
-                    }
                 }
-                log::info!("y: {}",y);
             }
             KeyCode::Left => {
-                // if x > 0 {
-                if x_word_index > 0 && y < height {
-                    x_word_index -= 1;
-                    let mut x_cursor = 0;
-                    for word_len in self.document.rows[y]
-                        .word_width_index.clone().iter().take(x_word_index) {
-                        x_cursor = x_cursor + word_len;
-                    }
-                    x = x_cursor;
+                if x_word_index > 0 {
+                    x_word_index = x_word_index.saturating_sub(1);
                 } else if y > 0 {
-                    y -= 1;
-                    if let Some(row) = self.document.row(y) {
-                        // x = row.len();
-                        let mut x_cursor = 0;
-                        for word_len in self.document.rows[y]
-                            .word_width_index.clone().iter() {
-                            x_cursor = x_cursor + word_len;
-                        }
-                        x_word_index = self.document.rows[y]
-                            .word_width_index
-                            .len();
-                        x = x_cursor;
-                    } else {
-                        x = 0;
-                    }
+                    y = y.saturating_sub(1);
+                    x_word_index = row_len(y);
                 }
             }
             KeyCode::Right => {
-                // if x < width {
-                if y < height && x_word_index < self.document.rows[y].word_width_index.len() {
-                    x_word_index += 1;
-                    let mut x_cursor = 0;
-                    for word_len in self.document.rows[y]
-                        .word_width_index.clone().iter().take(x_word_index) {
-                        x_cursor = x_cursor + word_len;
-                    }
-
-                    x = x_cursor;
-                    // // x += 1;
-                    // let mut index = x.saturating_add(1);
-                    // let str = &self.document.rows[y].string;
-                    // while index < str.len()
-                    //     && !str.is_char_boundary(index) {
-                    //     index += 1;
-                    // }
-                    //
-                    // if index - x > 1 {
-                    //     x += 2;
-                    // } else {
-                    //     x += 1;
-                    // }
-                } else if y < height - 1 {
-                    y += 1;
-                    x = 0;
+                if x_word_index < row_len(y) {
+                    x_word_index = x_word_index.saturating_add(1);
+                } else if y.saturating_add(1) < height {
+                    y = y.saturating_add(1);
                     x_word_index = 0;
                 }
             }
-            KeyCode::PageUp => {
-                y = if y > terminal_height {
-                    y.saturating_sub(terminal_height)
-                } else {
-                    0
+            KeyCode::PageUp => y = y.saturating_sub(terminal_height),
+            KeyCode::PageDown => y = cmp::min(y.saturating_add(terminal_height), height),
+            KeyCode::Home => x_word_index = 0,
+            KeyCode::End => x_word_index = row_len(y),
+            _ => (),
+        }
+        self.set_cursor(x_word_index, y);
+    }
+    fn move_word(&mut self, direction: SearchDirection) {
+        let Position { x_word_index, y, .. } = self.cursor_position;
+        let row_len = self.document.row(y).map_or(0, Row::len);
+        match direction {
+            SearchDirection::Backward if x_word_index == 0 && y > 0 => {
+                let y = y.saturating_sub(1);
+                self.set_cursor(self.document.row(y).map_or(0, Row::len), y);
+            }
+            SearchDirection::Forward if x_word_index >= row_len && y.saturating_add(1) < self.document.len() => {
+                self.set_cursor(0, y.saturating_add(1));
+            }
+            SearchDirection::Backward => {
+                let index = self.document.row(y).map_or(0, |row| row.previous_word_start(x_word_index));
+                self.set_cursor(index, y);
+            }
+            SearchDirection::Forward => {
+                let index = self.document.row(y).map_or(0, |row| row.next_word_start(x_word_index));
+                self.set_cursor(index, y);
+            }
+        }
+    }
+    /// Moves the cursor to grapheme `x_word_index` of row `y`, clamped to the
+    /// length of the row.
+    fn set_cursor(&mut self, x_word_index: usize, y: usize) {
+        let x_word_index = cmp::min(x_word_index, self.document.row(y).map_or(0, Row::len));
+        let x = self.document.row(y).map_or(0, |row| row.column_of(x_word_index));
+        self.cursor_position = Position { x, x_word_index, y }
+    }
+    fn position_at(&self, x_word_index: usize, y: usize) -> Position {
+        let x_word_index = cmp::min(x_word_index, self.document.row(y).map_or(0, Row::len));
+        let x = self.document.row(y).map_or(0, |row| row.column_of(x_word_index));
+        Position { x, x_word_index, y }
+    }
+    fn row_len(&self, y: usize) -> usize {
+        self.document.row(y).map_or(0, Row::len)
+    }
+    fn first_non_blank(&self, y: usize) -> usize {
+        self.document.row(y).map_or(0, Row::first_non_blank)
+    }
+    /// Keeps the cursor on a grapheme outside of insert mode, like Vim does.
+    fn clamp_vim_cursor(&mut self) {
+        if matches!(self.vim_mode(), Some(mode) if mode != Mode::Insert) {
+            let Position { x_word_index, y, .. } = self.cursor_position;
+            let last = self.row_len(y).saturating_sub(1);
+            if x_word_index > last {
+                self.set_cursor(last, y);
+            }
+        }
+    }
+    fn vim_action(&mut self, action: Action) {
+        match action {
+            Action::Move(motion, count) => {
+                if let Some(target) = self.motion_target(motion, count) {
+                    self.cursor_position = target;
                 }
             }
-            KeyCode::PageDown => {
-                y = if y.saturating_add(terminal_height) < height {
-                    y.saturating_add(terminal_height)
+            Action::Operate(operator, motion, count) => self.operate(operator, motion, count),
+            Action::Insert(insert) => self.start_insert(insert),
+            Action::Visual(_) => self.selection_anchor = self.cursor_position.clone(),
+            Action::VisualOperate(operator, mode) => {
+                let anchor = self.selection_anchor.clone();
+                let cursor = self.cursor_position.clone();
+                let (from, to) = ordered(anchor, cursor);
+                if mode == Mode::VisualLine {
+                    self.operate_lines(operator, from.y, to.y);
                 } else {
-                    height
+                    let to = self.position_at(to.x_word_index.saturating_add(1), to.y);
+                    self.operate_range(operator, from, to);
                 }
             }
-            KeyCode::Home => x = 0,
-            KeyCode::End => {
-                let mut x_cursor_position: usize = 0;
-                for word_space in self.document.rows[y].word_width_index.clone() {
-                    x_cursor_position = x_cursor_position + word_space;
+            Action::Paste { before, count } => self.paste(before, count),
+            Action::Repeat(count) => {
+                let keys = self.vim.as_ref().map_or_else(Vec::new, |vim| vim.repeat_keys(count));
+                for key in keys {
+                    self.process_key(key);
                 }
-                x = x_cursor_position
             }
-            _ => (),
+            Action::CommandLine => self.command_line(),
+            Action::Record(register) => self.start_recording(register),
+            Action::StopRecording => self.stop_recording(),
+            Action::Play(register, count) => self.play_macro(register, count),
+            Action::Escape => {
+                let Position { x_word_index, y, .. } = self.cursor_position;
+                self.set_cursor(x_word_index.saturating_sub(1), y);
+            }
+            Action::Passthrough => (),
         }
-        width = if let Some(row) = self.document.row(y) {
-            row.len()
+    }
+    /// Where `motion` repeated `count` times takes the cursor, if anywhere.
+    fn motion_target(&self, motion: Motion, count: Option<usize>) -> Option<Position> {
+        let Position { x_word_index, y, .. } = self.cursor_position;
+        let times = count.unwrap_or(1);
+        let last_line = self.document.len().saturating_sub(1);
+        let line_of = |count: Option<usize>, default: usize| {
+            count.map_or(default, |line| cmp::min(line.saturating_sub(1), last_line))
+        };
+        let row = self.document.row(y);
+        let (x_word_index, y) = match motion {
+            Motion::Left => (x_word_index.saturating_sub(times), y),
+            Motion::Right => (x_word_index.saturating_add(times), y),
+            Motion::Up => (x_word_index, y.saturating_sub(times)),
+            Motion::Down => (x_word_index, cmp::min(y.saturating_add(times), last_line)),
+            Motion::WordForward => (0..times).fold((x_word_index, y), |at, _| self.next_word(at)),
+            Motion::WordBackward => (0..times).fold((x_word_index, y), |at, _| self.previous_word(at)),
+            Motion::WordEnd => (0..times).fold((x_word_index, y), |at, _| self.word_end(at)),
+            Motion::LineStart => (0, y),
+            Motion::LineEnd => (self.row_len(y), y),
+            Motion::FirstLine => {
+                let y = line_of(count, 0);
+                (self.first_non_blank(y), y)
+            }
+            Motion::LastLine => {
+                let y = line_of(count, last_line);
+                (self.first_non_blank(y), y)
+            }
+            Motion::FindForward(c) => (row?.find_grapheme(c, x_word_index, times, SearchDirection::Forward)?, y),
+            Motion::FindBackward(c) => (row?.find_grapheme(c, x_word_index, times, SearchDirection::Backward)?, y),
+            Motion::TillForward(c) => {
+                let found = row?.find_grapheme(c, x_word_index, times, SearchDirection::Forward)?;
+                (found.saturating_sub(1), y)
+            }
+            Motion::TillBackward(c) => {
+                let found = row?.find_grapheme(c, x_word_index, times, SearchDirection::Backward)?;
+                (found.saturating_add(1), y)
+            }
+            Motion::CurrentLine => (x_word_index, cmp::min(y.saturating_add(times).saturating_sub(1), last_line)),
+        };
+        Some(self.position_at(x_word_index, y))
+    }
+    fn next_word(&self, (x_word_index, y): (usize, usize)) -> (usize, usize) {
+        let next = self.document.row(y).map_or(0, |row| row.next_word_start(x_word_index));
+        if next < self.row_len(y) || y.saturating_add(1) >= self.document.len() {
+            return (next, y);
+        }
+        let y = y.saturating_add(1);
+        (self.first_non_blank(y), y)
+    }
+    fn previous_word(&self, (x_word_index, y): (usize, usize)) -> (usize, usize) {
+        let start = self.document.row(y).map_or(0, |row| row.previous_word_start(x_word_index));
+        if (start > 0 || self.first_non_blank(y) < x_word_index) || y == 0 {
+            return (start, y);
+        }
+        let y = y.saturating_sub(1);
+        let end = self.row_len(y);
+        (self.document.row(y).map_or(0, |row| row.previous_word_start(end)), y)
+    }
+    fn word_end(&self, (x_word_index, y): (usize, usize)) -> (usize, usize) {
+        let mut at = x_word_index.saturating_add(1);
+        for line in y..self.document.len() {
+            if let Some(end) = self.document.row(line).and_then(|row| row.word_end_from(at)) {
+                return (end, line);
+            }
+            at = 0;
+        }
+        (x_word_index, y)
+    }
+    fn operate(&mut self, operator: Operator, motion: Motion, count: Option<usize>) {
+        let start = self.cursor_position.clone();
+        let on_word = self.document.row(start.y).map_or(false, |row| {
+            !row.slice(start.x_word_index, start.x_word_index.saturating_add(1)).trim().is_empty()
+        });
+        let mut motion = motion;
+        let target = if operator == Operator::Change && motion == Motion::WordForward && on_word {
+            // Like in Vim, `cw` on a word changes up to the end of the word.
+            motion = Motion::WordEnd;
+            let end = self
+                .document
+                .row(start.y)
+                .and_then(|row| row.word_end_from(start.x_word_index))
+                .unwrap_or(start.x_word_index);
+            let end = (1..count.unwrap_or(1)).fold((end, start.y), |at, _| self.word_end(at));
+            Some(self.position_at(end.0, end.1))
         } else {
-            0
+            self.motion_target(motion, count)
         };
-
-        // let mut x_cursor_position: usize = 0;
-        // for word_space in self.document.rows[y].word_width_index.clone() {
-        //     x_cursor_position = x_cursor_position + word_space;
-        // }
-        // if x > x_cursor_position {
-        //     x = x_cursor_position;
-        // }
-        // if x > width {
-        //     x = width;
-        // }
-
-        self.cursor_position = Position { x, x_word_index, y }
+        let target = match target {
+            Some(target) => target,
+            None => {
+                if let Some(vim) = &mut self.vim {
+                    vim.set_mode(Mode::Normal);
+                }
+                return;
+            }
+        };
+        if motion.is_linewise() {
+            self.operate_lines(operator, cmp::min(start.y, target.y), cmp::max(start.y, target.y));
+            return;
+        }
+        let (from, mut to) = ordered(start, target);
+        if motion.is_inclusive() {
+            to = self.position_at(to.x_word_index.saturating_add(1), to.y);
+        }
+        if motion == Motion::WordForward && to.y > from.y {
+            to = self.position_at(self.row_len(from.y), from.y);
+        }
+        self.operate_range(operator, from, to);
     }
-    fn draw_welcome_message(&self) {
+    fn operate_range(&mut self, operator: Operator, from: Position, to: Position) {
+        self.register = Some(Register {
+            text: self.document.text(&from, &to),
+            linewise: false,
+        });
+        if operator != Operator::Yank {
+            self.document.delete_range(&from, &to);
+        }
+        self.cursor_position = self.position_at(from.x_word_index, from.y);
+    }
+    fn operate_lines(&mut self, operator: Operator, first: usize, last: usize) {
+        let end = self.position_at(self.row_len(last), last);
+        let text = self.document.text(&self.position_at(0, first), &end);
+        self.register = Some(Register { text, linewise: true });
+        match operator {
+            Operator::Yank => {
+                let x_word_index = self.cursor_position.x_word_index;
+                self.set_cursor(x_word_index, first);
+            }
+            Operator::Delete => {
+                self.document.delete_rows(first, last);
+                let y = cmp::min(first, self.document.len().saturating_sub(1));
+                self.set_cursor(self.first_non_blank(y), y);
+            }
+            Operator::Change => {
+                self.document.delete_rows(first, last);
+                self.document.insert_newline(&self.position_at(0, first));
+                self.set_cursor(0, first);
+            }
+        }
+    }
+    fn paste(&mut self, before: bool, count: usize) {
+        let register = match &self.register {
+            Some(register) => register.clone(),
+            None => return,
+        };
+        let Position { x_word_index, y, .. } = self.cursor_position;
+        if register.linewise {
+            let text = vec![register.text; count].join("\n");
+            if self.document.is_empty() {
+                self.document.insert_str(&mut Position::default(), &text);
+                self.set_cursor(self.first_non_blank(0), 0);
+            } else if before {
+                self.document.insert_str(&mut self.position_at(0, y), &format!("{}\n", text));
+                self.set_cursor(self.first_non_blank(y), y);
+            } else {
+                let mut at = self.position_at(self.row_len(y), y);
+                self.document.insert_str(&mut at, &format!("\n{}", text));
+                let y = y.saturating_add(1);
+                self.set_cursor(self.first_non_blank(y), y);
+            }
+            return;
+        }
+        let x_word_index = if before || self.row_len(y) == 0 {
+            x_word_index
+        } else {
+            x_word_index.saturating_add(1)
+        };
+        let mut at = self.position_at(x_word_index, y);
+        self.document.insert_str(&mut at, &register.text.repeat(count));
+        self.set_cursor(at.x_word_index.saturating_sub(1), at.y);
+    }
+    fn start_insert(&mut self, insert: Insert) {
+        let Position { x_word_index, y, .. } = self.cursor_position;
+        match insert {
+            Insert::Before => (),
+            Insert::After => self.set_cursor(x_word_index.saturating_add(1), y),
+            Insert::LineStart => self.set_cursor(self.first_non_blank(y), y),
+            Insert::LineEnd => self.set_cursor(self.row_len(y), y),
+            Insert::LineBelow => {
+                self.document.insert_newline(&self.position_at(self.row_len(y), y));
+                let below = cmp::min(y.saturating_add(1), self.document.len().saturating_sub(1));
+                self.set_cursor(0, below);
+            }
+            Insert::LineAbove => {
+                self.document.insert_newline(&self.position_at(0, y));
+                self.set_cursor(0, y);
+            }
+        }
+    }
+    /// How rows show whitespace with the current config.
+    fn whitespace(&self) -> Whitespace {
+        Whitespace {
+            visible: self.config.visible_whitespace,
+            trailing: self.config.trailing_whitespace,
+        }
+    }
+    /// The display columns of row `index` covered by the visual mode
+    /// selection.
+    fn selected_columns(&self, index: usize) -> Option<(usize, usize)> {
+        let mode = self.vim_mode().filter(|mode| mode.is_visual())?;
+        let (from, to) = ordered(self.selection_anchor.clone(), self.cursor_position.clone());
+        if index < from.y || index > to.y {
+            return None;
+        }
+        let row = self.document.row(index)?;
+        if mode == Mode::VisualLine {
+            return Some((0, cmp::max(row.width(), 1)));
+        }
+        let start = if index == from.y { row.column_of(from.x_word_index) } else { 0 };
+        let end = if index == to.y {
+            row.column_of(to.x_word_index.saturating_add(1))
+        } else {
+            row.width()
+        };
+        Some((start, cmp::max(end, start.saturating_add(1))))
+    }
+    fn draw_welcome_message(&mut self, y: u16) {
         let mut welcome_message = format!("Hecto editor -- version {}", VERSION);
         let width = self.terminal.size().width as usize;
         let len = welcome_message.len();
@@ -440,33 +1254,177 @@ impl Editor {
         let spaces = " ".repeat(padding.saturating_sub(1));
         welcome_message = format!("~{}{}", spaces, welcome_message);
         welcome_message.truncate(width);
-        println!("{}\r", welcome_message);
+        self.terminal.set_string(0, y, &welcome_message, self.theme.text());
     }
-    pub fn draw_row(&self, row: &Row) {
-        let width = self.terminal.size().width as usize;
-        let start = self.offset.x;
-        let end = self.offset.x.saturating_add(width);
-        let row = row.render(start, end);
-        println!("{}\r", row)
+    /// Draws the part of row `index` between the display columns `start`
+    /// and `end` on screen line `y`, with its line number if `first_line`.
+    #[allow(clippy::cast_possible_truncation)]
+    pub fn draw_row(&mut self, y: u16, index: usize, start: usize, end: usize, first_line: bool) {
+        let gutter_width = self.gutter_width();
+        if gutter_width > 0 {
+            let number = if first_line {
+                index.saturating_add(1).to_string()
+            } else {
+                String::new()
+            };
+            let gutter = format!("{:>width$} ", number, width = gutter_width.saturating_sub(1));
+            self.terminal.set_string(0, y, &gutter, self.theme.gutter());
+        }
+        if let Some(row) = self.document.row(index) {
+            let mut x = gutter_width as u16;
+            for (text, highlighting_type) in row.render(start, end, self.whitespace()) {
+                x = self.terminal.set_string(x, y, &text, self.theme.syntax(highlighting_type));
+            }
+        }
+        if self.config.cursor_line && index == self.cursor_position.y {
+            let width = end.saturating_sub(start) as u16;
+            self.terminal
+                .set_style(gutter_width as u16, y, width, self.theme.cursor_line());
+        }
+        self.draw_rulers(y, start, end);
+        if let Some((selection_start, selection_end)) = self.selected_columns(index) {
+            let from = cmp::max(selection_start, start);
+            let to = cmp::min(selection_end, end);
+            if from < to {
+                let x = gutter_width.saturating_add(from.saturating_sub(start)) as u16;
+                self.terminal
+                    .set_style(x, y, to.saturating_sub(from) as u16, self.theme.selection());
+            }
+        }
+    }
+    /// Marks the configured ruler columns that fall between the display
+    /// columns `start` and `end` on screen line `y`.
+    #[allow(clippy::cast_possible_truncation)]
+    fn draw_rulers(&mut self, y: u16, start: usize, end: usize) {
+        let gutter_width = self.gutter_width();
+        for ruler in &self.config.rulers {
+            // Rulers count columns from 1.
+            let column = ruler.saturating_sub(1);
+            if *ruler > 0 && (start..end).contains(&column) {
+                let x = gutter_width.saturating_add(column.saturating_sub(start)) as u16;
+                self.terminal.set_style(x, y, 1, self.theme.ruler());
+            }
+        }
     }
     #[allow(clippy::integer_division, clippy::integer_arithmetic)]
-    fn draw_rows(&self) {
+    fn draw_rows(&mut self) {
         let height = self.terminal.size().height;
-        for terminal_row in 0..height {
-            Terminal::clear_current_line();
-            if let Some(row) = self
-                .document
-                .row(self.offset.y.saturating_add(terminal_row as usize))
-            {
-                self.draw_row(row);
-            } else if self.document.is_empty() && terminal_row == height / 3 {
-                self.draw_welcome_message();
+        let width = self.text_width();
+        let mut index = self.offset.y;
+        let mut terminal_row = 0;
+        while terminal_row < height {
+            if index < self.document.len() {
+                if self.config.soft_wrap {
+                    let starts = self.document.row(index).map_or_else(Vec::new, |row| row.wrap(width));
+                    for (line, start) in starts.iter().enumerate() {
+                        if terminal_row >= height {
+                            break;
+                        }
+                        let end = starts.get(line + 1).copied().unwrap_or(start + width);
+                        self.draw_row(terminal_row, index, *start, end, line == 0);
+                        terminal_row += 1;
+                    }
+                } else {
+                    let start = self.offset.x;
+                    self.draw_row(terminal_row, index, start, start + width, true);
+                    terminal_row += 1;
+                }
+                index += 1;
+                continue;
+            }
+            if self.document.is_empty() && terminal_row == height / 3 {
+                self.draw_welcome_message(terminal_row);
             } else {
-                println!("~\r");
+                self.terminal.set_string(0, terminal_row, "~", self.theme.text());
+                self.draw_rulers(terminal_row, self.offset.x, self.offset.x + width);
             }
+            terminal_row += 1;
         }
     }
-    fn draw_status_bar(&self) {
+    fn help_lines(&self) -> Vec<String> {
+        let mut lines = vec![String::from("Key bindings (arrows to scroll, any other key to close)")];
+        for command in &Command::ALL {
+            let chords = self.keymap.chords_for(*command);
+            lines.push(format!(
+                "  {:<28}{:<18}{}",
+                chords.join(", "),
+                command.name(),
+                command.description()
+            ));
+        }
+        lines
+    }
+    #[allow(clippy::cast_possible_truncation)]
+    fn draw_help(&mut self) {
+        let height = self.terminal.size().height as usize;
+        let offset = self.help_offset.unwrap_or(0);
+        for (y, line) in self.help_lines().iter().skip(offset).take(height).enumerate() {
+            let style = if y == 0 && offset == 0 {
+                self.theme.status_bar()
+            } else {
+                self.theme.text()
+            };
+            self.terminal.set_string(0, y as u16, line, style);
+        }
+    }
+    fn scroll_help(&mut self, key: Key) {
+        let height = self.terminal.size().height as usize;
+        let max_offset = self.help_lines().len().saturating_sub(height);
+        let offset = self.help_offset.unwrap_or(0);
+        let offset = match key.code() {
+            KeyCode::Up => offset.saturating_sub(1),
+            KeyCode::Down => offset.saturating_add(1),
+            KeyCode::PageUp => offset.saturating_sub(height),
+            KeyCode::PageDown => offset.saturating_add(height),
+            _ => {
+                self.help_offset = None;
+                return;
+            }
+        };
+        self.help_offset = Some(cmp::min(offset, max_offset));
+    }
+    /// Draws the command palette entries right above the status bar, with
+    /// the bound keys on the right.
+    #[allow(clippy::cast_possible_truncation)]
+    fn draw_palette(&mut self) {
+        let palette = match &self.palette {
+            Some(palette) => palette,
+            None => return,
+        };
+        let width = self.terminal.size().width as usize;
+        let height = self.terminal.size().height as usize;
+        let lines = cmp::min(cmp::min(cmp::max(palette.matches().len(), 1), PALETTE_HEIGHT), height);
+        let first = palette
+            .selected_index()
+            .saturating_sub(lines.saturating_sub(1));
+        let top = height.saturating_sub(lines);
+        for line in 0..lines {
+            let y = top.saturating_add(line) as u16;
+            let index = first.saturating_add(line);
+            let command = match palette.matches().get(index) {
+                Some(command) => *command,
+                None => {
+                    let text = format!("{:width$}", " No matching commands", width = width);
+                    self.terminal.set_string(0, y, &text, self.theme.status_bar());
+                    continue;
+                }
+            };
+            let keys = format!("  {} ", self.keymap.chords_for(command).join(", "));
+            let room = width.saturating_sub(keys.len());
+            let entry: String = format!(" {:<18}{}", command.name(), command.description())
+                .chars()
+                .take(room)
+                .collect();
+            let text = format!("{:room$}{}", entry, keys, room = room);
+            let style = if index == palette.selected_index() {
+                self.theme.status_bar().patch(self.theme.selection())
+            } else {
+                self.theme.status_bar()
+            };
+            self.terminal.set_string(0, y, &text, style);
+        }
+    }
+    fn draw_status_bar(&mut self) {
         let mut status;
         let width = self.terminal.size().width as usize;
         let modified_indicator = if self.document.is_dirty() {
@@ -486,6 +1444,12 @@ impl Editor {
             self.document.len(),
             modified_indicator
         );
+        if let Some(register) = self.macros.recording() {
+            status = format!("REC @{} | {}", register, status);
+        }
+        if let Some(mode) = self.vim_mode() {
+            status = format!("{} | {}", mode.name(), status);
+        }
 
         let line_indicator = format!(
             "{} | {}/{}",
@@ -498,56 +1462,157 @@ impl Editor {
         status.push_str(&" ".repeat(width.saturating_sub(len)));
         status = format!("{}{}", status, line_indicator);
         status.truncate(width);
-        Terminal::set_bg_color(STATUS_BG_COLOR);
-        Terminal::set_fg_color(STATUS_FG_COLOR);
-        println!("{}\r", status);
-        Terminal::reset_color();
+        let y = self.terminal.size().height;
+        self.terminal.set_string(0, y, &status, self.theme.status_bar());
     }
-    fn draw_message_bar(&self) {
-        Terminal::clear_current_line();
+    /// Draws the message bar and returns where the cursor goes if a
+    /// prompt is open. A prompt longer than the screen is scrolled to keep
+    /// its cursor, and a wide grapheme under it, visible.
+    fn draw_message_bar(&mut self) -> Option<Position> {
+        let width = self.terminal.size().width as usize;
         let message = &self.status_message;
-        if Instant::now() - message.time < Duration::new(5, 0) {
-            let mut text = message.text.clone();
-            text.truncate(self.terminal.size().width as usize);
-            print!("{}", text);
+        let timeout = Duration::from_secs(self.config.message_timeout);
+        let text = if self.prompt_cursor.is_some() || Instant::now() - message.time < timeout {
+            message.text.as_str()
+        } else {
+            ""
+        };
+        let skip = self
+            .prompt_cursor
+            .map_or(0, |column| column.saturating_add(2).saturating_sub(width));
+        let mut column: usize = 0;
+        let mut skipped: usize = 0;
+        let visible: String = text
+            .graphemes(true)
+            .filter(|grapheme| {
+                let start = column;
+                column = column.saturating_add(grapheme.width());
+                if start < skip {
+                    skipped = column;
+                }
+                start >= skip
+            })
+            .collect();
+        let padding = " ".repeat(width.saturating_sub(visible.width()));
+        let text = format!("{}{}", visible, padding);
+        let y = self.terminal.size().height.saturating_add(1);
+        self.terminal.set_string(0, y, &text, self.theme.message_bar());
+        self.prompt_cursor.map(|column| Position {
+            x: column.saturating_sub(skipped),
+            x_word_index: 0,
+            y: usize::from(y),
+        })
+    }
+    fn prompt<C>(
+        &mut self,
+        prompt: &str,
+        history: Option<History>,
+        callback: C,
+    ) -> Result<Option<String>, std::io::Error>
+        where
+            C: FnMut(&mut Self, Key, &mut String),
+    {
+        let result = self.prompt_input(prompt, history, callback)?;
+        Ok(result.filter(|result| !result.is_empty()))
+    }
+    /// Like `prompt`, but tells a cancelled prompt (`None`) apart from an
+    /// empty answer. Up and Down walk through earlier answers to prompts of
+    /// the same `history` kind.
+    fn prompt_input<C>(
+        &mut self,
+        prompt: &str,
+        history: Option<History>,
+        callback: C,
+    ) -> Result<Option<String>, std::io::Error>
+        where
+            C: FnMut(&mut Self, Key, &mut String),
+    {
+        let result = self.read_prompt(prompt, history, callback);
+        self.prompt_cursor = None;
+        self.status_message = StatusMessage::from(String::new());
+        let result = result?;
+        if let (Some(history), Some(answer)) = (history, &result) {
+            let entries = self.prompt_history.entry(history).or_default();
+            if !answer.is_empty() && entries.last() != Some(answer) {
+                entries.push(answer.clone());
+            }
         }
+        Ok(result)
     }
-    fn prompt<C>(&mut self, prompt: &str, mut callback: C) -> Result<Option<String>, std::io::Error>
+    fn read_prompt<C>(
+        &mut self,
+        prompt: &str,
+        history: Option<History>,
+        mut callback: C,
+    ) -> Result<Option<String>, std::io::Error>
         where
-            C: FnMut(&mut Self, KeyCode, &String),
+            C: FnMut(&mut Self, Key, &mut String),
     {
-        let mut result = String::new();
+        let mut input = Input::default();
+        let entries = history
+            .and_then(|history| self.prompt_history.get(&history))
+            .cloned()
+            .unwrap_or_default();
+        let mut history_index = entries.len();
+        let mut draft = String::new();
         loop {
-            self.status_message = StatusMessage::from(format!("{}{}", prompt, result));
+            self.status_message = StatusMessage::from(format!("{}{}", prompt, input.text()));
+            self.prompt_cursor = Some(prompt.width().saturating_add(input.cursor_column()));
             self.refresh_screen()?;
-            let keyevent = Terminal::read_key()?;
-            let key = keyevent.code;
-            match key {
-                KeyCode::Backspace => result.truncate(result.len().saturating_sub(1)),
-                // KeyCode::Char('\n') => break,
+            let key = match self.read_event()? {
+                Event::Key(keyevent) => Key::from(keyevent),
+                Event::Paste(text) => {
+                    let text: String = text.chars().filter(|c| !c.is_control()).collect();
+                    input.insert_str(&text);
+                    Key::new(KeyCode::Null, KeyModifiers::NONE)
+                }
+                _ => continue,
+            };
+            match key.code() {
                 KeyCode::Enter => break,
-                KeyCode::Char(c) => {
-                    if !c.is_control() {
-                        result.push(c);
-                    }
+                KeyCode::Esc => return Ok(None),
+                KeyCode::Backspace => input.delete_backward(),
+                KeyCode::Delete => input.delete_forward(),
+                KeyCode::Left => input.move_left(),
+                KeyCode::Right => input.move_right(),
+                KeyCode::Home => input.move_home(),
+                KeyCode::End => input.move_end(),
+                KeyCode::Char('w') if key.modifiers() == KeyModifiers::CONTROL => {
+                    input.delete_word_backward();
                 }
-                KeyCode::Esc => {
-                    result.truncate(0);
-                    break;
+                KeyCode::Up | KeyCode::Down if history.is_some() => {
+                    if history_index == entries.len() {
+                        draft = input.text().to_string();
+                    }
+                    history_index = if key.code() == KeyCode::Up {
+                        history_index.saturating_sub(1)
+                    } else {
+                        cmp::min(history_index.saturating_add(1), entries.len())
+                    };
+                    input.set_text(entries.get(history_index).unwrap_or(&draft).clone());
                 }
+                KeyCode::Char(c) if key.is_text() => input.insert(c),
                 _ => (),
             }
-            callback(self, key, &result);
-        }
-        self.status_message = StatusMessage::from(String::new());
-        if result.is_empty() {
-            return Ok(None);
+            let mut text = input.text().to_string();
+            callback(self, key, &mut text);
+            if text != input.text() {
+                input.set_text(text);
+            }
         }
-        Ok(Some(result))
+        Ok(Some(input.text().to_string()))
+    }
+}
+
+/// Returns two positions in document order.
+fn ordered(a: Position, b: Position) -> (Position, Position) {
+    if (a.y, a.x_word_index) <= (b.y, b.x_word_index) {
+        (a, b)
+    } else {
+        (b, a)
     }
 }
 
 fn die(e: std::io::Error) {
-    Terminal::clear_screen();
-    panic!(e);
+    panic!("{}", e);
 }
@@ -0,0 +1,364 @@
+use crate::config::OPTIONS;
+use std::cmp;
+use std::fs;
+use std::path::Path;
+
+/// Command names offered by tab completion.
+const COMMAND_NAMES: [&str; 7] = ["edit", "quit", "set", "substitute", "wq", "write", "xit"];
+
+/// A parsed `:` command line. Line numbers are 0-based row indices.
+#[derive(PartialEq, Debug)]
+pub enum ExCommand {
+    Write { file: Option<String>, quit: bool },
+    Quit { force: bool },
+    Edit { file: String, force: bool },
+    /// Option names with their values; a missing value turns a boolean
+    /// option on.
+    Set(Vec<(String, Option<String>)>),
+    Goto(usize),
+    Substitute {
+        first: usize,
+        last: usize,
+        pattern: String,
+        /// The replacement in `regex` syntax.
+        replacement: String,
+        global: bool,
+        ignore_case: bool,
+    },
+}
+
+/// Parses a command line typed after `:`. `current` and `last` are the
+/// cursor row and the last row, used to resolve `.`, `$` and `%`.
+pub fn parse(line: &str, current: usize, last: usize) -> Result<ExCommand, String> {
+    let line = line.trim().trim_start_matches(':');
+    let (range, rest) = parse_range(line, current, last)?;
+    let name_len = rest
+        .find(|c: char| !c.is_ascii_alphabetic())
+        .unwrap_or_else(|| rest.len());
+    let (name, rest) = rest.split_at(name_len);
+    let (force, rest) = match rest.strip_prefix('!') {
+        Some(rest) => (true, rest),
+        None => (false, rest),
+    };
+    let argument = Some(rest.trim()).filter(|argument| !argument.is_empty());
+    let command = match name {
+        "" if !force && argument.is_none() => match range {
+            Some((_, line)) => ExCommand::Goto(line),
+            None => return Err(String::from("Empty command")),
+        },
+        "w" | "write" => ExCommand::Write {
+            file: argument.map(String::from),
+            quit: false,
+        },
+        "wq" | "x" | "xit" => ExCommand::Write {
+            file: argument.map(String::from),
+            quit: true,
+        },
+        "q" | "quit" => ExCommand::Quit { force },
+        "e" | "edit" => ExCommand::Edit {
+            file: argument
+                .ok_or_else(|| String::from("No file name"))?
+                .to_string(),
+            force,
+        },
+        "se" | "set" => ExCommand::Set(
+            argument
+                .unwrap_or("")
+                .split_whitespace()
+                .map(parse_option)
+                .collect(),
+        ),
+        "s" | "substitute" => {
+            let (first, last) = range.unwrap_or((current, current));
+            parse_substitute(rest, first, last)?
+        }
+        _ => return Err(format!("Not an editor command: {}", line)),
+    };
+    if range.is_some() && !matches!(command, ExCommand::Goto(_) | ExCommand::Substitute { .. }) {
+        return Err(String::from("No range allowed"));
+    }
+    Ok(command)
+}
+
+/// Parses a leading `%`, `N`, `.`, `$` or `A,B` range into row indices.
+fn parse_range(line: &str, current: usize, last: usize) -> Result<(Option<(usize, usize)>, &str), String> {
+    if let Some(rest) = line.strip_prefix('%') {
+        return Ok((Some((0, last)), rest));
+    }
+    let (first, rest) = parse_address(line, current, last);
+    let first = match first {
+        Some(first) => first,
+        None => return Ok((None, rest)),
+    };
+    let (second, rest) = match rest.strip_prefix(',') {
+        Some(rest) => {
+            let (second, rest) = parse_address(rest, current, last);
+            (second.ok_or_else(|| String::from("Invalid range"))?, rest)
+        }
+        None => (first, rest),
+    };
+    if first > second {
+        return Err(String::from("Backwards range given"));
+    }
+    Ok((Some((first, cmp::min(second, last))), rest))
+}
+
+fn parse_address(text: &str, current: usize, last: usize) -> (Option<usize>, &str) {
+    if let Some(rest) = text.strip_prefix('.') {
+        return (Some(current), rest);
+    }
+    if let Some(rest) = text.strip_prefix('$') {
+        return (Some(last), rest);
+    }
+    let digits = text
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or_else(|| text.len());
+    match text[..digits].parse::<usize>() {
+        Ok(line) => (Some(line.saturating_sub(1)), &text[digits..]),
+        Err(_) => (None, text),
+    }
+}
+
+fn parse_option(text: &str) -> (String, Option<String>) {
+    let (name, value) = match text.split_once('=') {
+        Some((name, value)) => (name, Some(value.to_string())),
+        None => match text.strip_prefix("no") {
+            Some(name) if OPTIONS.contains(&option_name(name)) => (name, Some(String::from("false"))),
+            _ => (text, None),
+        },
+    };
+    (option_name(name).to_string(), value)
+}
+
+/// Maps the Vim names of options to ours.
+fn option_name(name: &str) -> &str {
+    match name {
+        "ts" | "tabstop" => "tab_width",
+        "nu" | "number" => "line_numbers",
+        "wrap" => "soft_wrap",
+        "colorscheme" => "theme",
+        "cursorline" => "cursor_line",
+        "colorcolumn" => "rulers",
+        "list" => "visible_whitespace",
+        _ => name,
+    }
+}
+
+/// Parses `/pattern/replacement/flags`, with any punctuation as the
+/// separator.
+fn parse_substitute(text: &str, first: usize, last: usize) -> Result<ExCommand, String> {
+    let mut chars = text.chars();
+    let separator = chars
+        .next()
+        .filter(|c| !c.is_alphanumeric() && !c.is_whitespace() && *c != '\\')
+        .ok_or_else(|| String::from("Usage: s/pattern/replacement/flags"))?;
+    let mut parts = vec![String::new()];
+    let mut escaped = false;
+    for c in chars {
+        if c == separator && !escaped && parts.len() < 3 {
+            parts.push(String::new());
+            continue;
+        }
+        let part = parts.last_mut().expect("parts is never empty");
+        if escaped {
+            if c != separator {
+                part.push('\\');
+            }
+            part.push(c);
+            escaped = false;
+        } else if c == '\\' {
+            escaped = true;
+        } else {
+            part.push(c);
+        }
+    }
+    if escaped {
+        parts.last_mut().expect("parts is never empty").push('\\');
+    }
+    let mut parts = parts.into_iter();
+    let pattern = parts.next().unwrap_or_default();
+    let replacement = parts.next().unwrap_or_default();
+    let flags = parts.next().unwrap_or_default();
+    if pattern.is_empty() {
+        return Err(String::from("Empty pattern"));
+    }
+    if let Some(flag) = flags.chars().find(|flag| *flag != 'g' && *flag != 'i') {
+        return Err(format!("Unknown flag: {}", flag));
+    }
+    Ok(ExCommand::Substitute {
+        first,
+        last,
+        pattern,
+        replacement: regex_replacement(&replacement),
+        global: flags.contains('g'),
+        ignore_case: flags.contains('i'),
+    })
+}
+
+/// Translates a Vim replacement, where `&` and `\1` refer to the match and
+/// its groups, into `regex` syntax.
+fn regex_replacement(replacement: &str) -> String {
+    let mut result = String::new();
+    let mut chars = replacement.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => match chars.next() {
+                Some(digit) if digit.is_ascii_digit() => {
+                    result.push_str(&format!("${{{}}}", digit));
+                }
+                Some('$') => result.push_str("$$"),
+                Some(other) => result.push(other),
+                None => result.push('\\'),
+            },
+            '&' => result.push_str("${0}"),
+            '$' => result.push_str("$$"),
+            _ => result.push(c),
+        }
+    }
+    result
+}
+
+/// Returns the command lines `input` can be completed to: command names,
+/// option names after `set`, and file paths after `edit` and `write`.
+pub fn complete(input: &str) -> Vec<String> {
+    let (command, argument) = match input.split_once(' ') {
+        Some((command, argument)) => (command, argument),
+        None => {
+            return COMMAND_NAMES
+                .iter()
+                .filter(|name| name.starts_with(input))
+                .map(|name| name.to_string())
+                .collect()
+        }
+    };
+    match command {
+        "se" | "set" => {
+            let (done, word) = match argument.rsplit_once(' ') {
+                Some((done, word)) => (format!("{} ", done), word),
+                None => (String::new(), argument),
+            };
+            OPTIONS
+                .iter()
+                .filter(|name| name.starts_with(word))
+                .map(|name| format!("{} {}{}", command, done, name))
+                .collect()
+        }
+        "e" | "edit" | "w" | "write" | "wq" | "x" | "xit" => complete_path(argument)
+            .into_iter()
+            .map(|path| format!("{} {}", command, path))
+            .collect(),
+        _ => Vec::new(),
+    }
+}
+
+fn complete_path(partial: &str) -> Vec<String> {
+    let (directory, prefix) = match partial.rfind('/') {
+        Some(index) => partial.split_at(index.saturating_add(1)),
+        None => ("", partial),
+    };
+    let entries = match fs::read_dir(if directory.is_empty() { Path::new(".") } else { Path::new(directory) }) {
+        Ok(entries) => entries,
+        Err(_) => return Vec::new(),
+    };
+    let mut paths: Vec<String> = entries
+        .filter_map(Result::ok)
+        .filter_map(|entry| {
+            let name = entry.file_name().into_string().ok()?;
+            if !name.starts_with(prefix) || (name.starts_with('.') && !prefix.starts_with('.')) {
+                return None;
+            }
+            let suffix = if entry.path().is_dir() { "/" } else { "" };
+            Some(format!("{}{}{}", directory, name, suffix))
+        })
+        .collect();
+    paths.sort();
+    paths
+}
+
+#[cfg(test)]
+mod test_super {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(parse("w", 0, 9), Ok(ExCommand::Write { file: None, quit: false }));
+        assert_eq!(
+            parse(":wq out.txt", 0, 9),
+            Ok(ExCommand::Write {
+                file: Some(String::from("out.txt")),
+                quit: true
+            })
+        );
+        assert_eq!(parse("q!", 0, 9), Ok(ExCommand::Quit { force: true }));
+        assert_eq!(parse("42", 0, 99), Ok(ExCommand::Goto(41)));
+        assert_eq!(parse("42", 0, 9), Ok(ExCommand::Goto(9)));
+        assert_eq!(parse("$", 0, 9), Ok(ExCommand::Goto(9)));
+        assert_eq!(
+            parse("set nu ts=2 nowrap", 0, 9),
+            Ok(ExCommand::Set(vec![
+                (String::from("line_numbers"), None),
+                (String::from("tab_width"), Some(String::from("2"))),
+                (String::from("soft_wrap"), Some(String::from("false"))),
+            ]))
+        );
+        assert!(parse("e", 0, 9).is_err());
+        assert!(parse("3w", 0, 9).is_err());
+        assert!(parse("frobnicate", 0, 9).is_err());
+    }
+
+    #[test]
+    fn test_parse_substitute() {
+        assert_eq!(
+            parse(r"%s/a\/b/[&] \1 $5/g", 3, 9),
+            Ok(ExCommand::Substitute {
+                first: 0,
+                last: 9,
+                pattern: String::from("a/b"),
+                replacement: String::from("[${0}] ${1} $$5"),
+                global: true,
+                ignore_case: false,
+            })
+        );
+        assert_eq!(
+            parse(r"2,.s#(\d+)#x#i", 3, 9),
+            Ok(ExCommand::Substitute {
+                first: 1,
+                last: 3,
+                pattern: String::from(r"(\d+)"),
+                replacement: String::from("x"),
+                global: false,
+                ignore_case: true,
+            })
+        );
+        assert!(parse("s//x/", 0, 9).is_err());
+        assert!(parse("s/a/b/z", 0, 9).is_err());
+        assert!(parse("5,2s/a/b/", 0, 9).is_err());
+    }
+
+    #[test]
+    fn test_complete() {
+        assert_eq!(complete("s"), vec!["set", "substitute"]);
+        assert_eq!(complete("w"), vec!["wq", "write"]);
+        assert_eq!(complete("set nu tab"), vec!["set nu tab_width"]);
+
+        let dir = std::env::temp_dir().join(format!("hecto-complete-{}", std::process::id()));
+        fs::create_dir_all(dir.join("src")).unwrap();
+        for file in ["Cargo.toml", "Cargo.lock", ".hidden", "src/editor.rs"] {
+            fs::write(dir.join(file), "").unwrap();
+        }
+        let dir_name = dir.display().to_string();
+        let complete_in = |partial: &str| {
+            complete(&format!("e {}/{}", dir_name, partial))
+                .into_iter()
+                .map(|line| line.replacen(&format!("{}/", dir_name), "", 1))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(complete_in("Cargo.to"), vec!["e Cargo.toml"]);
+        assert_eq!(complete_in("Cargo."), vec!["e Cargo.lock", "e Cargo.toml"]);
+        assert_eq!(complete_in(""), vec!["e Cargo.lock", "e Cargo.toml", "e src/"]);
+        assert_eq!(complete_in(".h"), vec!["e .hidden"]);
+        assert_eq!(complete_in("src/ed"), vec!["e src/editor.rs"]);
+        assert!(complete_in("missing/").is_empty());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,17 +1,271 @@
+use crate::config::config_dir;
+use regex::Regex;
+use serde::Deserialize;
+use std::cmp;
+use std::collections::HashSet;
+use std::fs;
+use std::path::Path;
+use std::sync::OnceLock;
+
+const BUILTIN_SYNTAXES: [(&str, &str); 12] = [
+    ("rust.toml", include_str!("../syntax/rust.toml")),
+    ("c.toml", include_str!("../syntax/c.toml")),
+    ("cpp.toml", include_str!("../syntax/cpp.toml")),
+    ("python.toml", include_str!("../syntax/python.toml")),
+    ("go.toml", include_str!("../syntax/go.toml")),
+    ("javascript.toml", include_str!("../syntax/javascript.toml")),
+    ("typescript.toml", include_str!("../syntax/typescript.toml")),
+    ("shell.toml", include_str!("../syntax/shell.toml")),
+    ("toml.toml", include_str!("../syntax/toml.toml")),
+    ("yaml.toml", include_str!("../syntax/yaml.toml")),
+    ("json.toml", include_str!("../syntax/json.toml")),
+    ("markdown.toml", include_str!("../syntax/markdown.toml")),
+];
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SyntaxFile {
+    name: String,
+    #[serde(default)]
+    extensions: Vec<String>,
+    #[serde(default)]
+    first_lines: Vec<String>,
+    #[serde(default)]
+    numbers: bool,
+    #[serde(default)]
+    number_prefixes: Vec<String>,
+    number_separator: Option<char>,
+    #[serde(default)]
+    number_exponents: bool,
+    #[serde(default)]
+    number_suffixes: Vec<String>,
+    line_comment: Option<String>,
+    #[serde(default)]
+    doc_comments: Vec<String>,
+    block_comment: Option<(String, String)>,
+    #[serde(default)]
+    nested_comments: bool,
+    #[serde(default)]
+    strings: Vec<String>,
+    #[serde(default)]
+    multiline_strings: Vec<String>,
+    #[serde(default)]
+    string_prefixes: Vec<String>,
+    #[serde(default)]
+    raw_string_prefixes: Vec<String>,
+    escape: Option<char>,
+    character: Option<char>,
+    #[serde(default)]
+    primary_keywords: Vec<String>,
+    #[serde(default)]
+    secondary_keywords: Vec<String>,
+    #[serde(default)]
+    functions: bool,
+    #[serde(default)]
+    function_keywords: Vec<String>,
+    #[serde(default)]
+    macros: bool,
+    #[serde(default)]
+    lifetimes: bool,
+    #[serde(default)]
+    attributes: bool,
+    #[serde(default)]
+    type_names: bool,
+}
+
+/// A language as described by a syntax definition file: which files it
+/// applies to and how to highlight them.
+struct Syntax {
+    name: String,
+    extensions: Vec<String>,
+    /// Patterns for the first line, such as a shebang.
+    first_lines: Vec<Regex>,
+    hl_opts: HighlightingOptions,
+}
+
+impl Syntax {
+    fn from_toml(source: &str) -> Result<Self, String> {
+        let file: SyntaxFile = toml::from_str(source).map_err(|err| err.to_string())?;
+        let first_lines = file
+            .first_lines
+            .iter()
+            .map(|pattern| Regex::new(pattern).map_err(|err| err.to_string()))
+            .collect::<Result<Vec<Regex>, String>>()?;
+        if file
+            .strings
+            .iter()
+            .chain(&file.multiline_strings)
+            .chain(&file.string_prefixes)
+            .chain(&file.raw_string_prefixes)
+            .chain(&file.doc_comments)
+            .chain(&file.number_suffixes)
+            .any(String::is_empty)
+            || file.line_comment.as_deref() == Some("")
+            || matches!(&file.block_comment, Some((start, end)) if start.is_empty() || end.is_empty())
+        {
+            return Err(String::from("empty comment, string or number delimiter"));
+        }
+        let number_prefixes = file
+            .number_prefixes
+            .iter()
+            .map(|prefix| match prefix.to_ascii_lowercase().chars().last() {
+                Some('x') => Ok((prefix.clone(), 16)),
+                Some('o') => Ok((prefix.clone(), 8)),
+                Some('b') => Ok((prefix.clone(), 2)),
+                _ => Err(format!("number prefix `{}` does not end in x, o or b", prefix)),
+            })
+            .collect::<Result<Vec<(String, u32)>, String>>()?;
+        let mut number_suffixes = file.number_suffixes;
+        // The longest suffix wins, so `u64` is not read as `u`.
+        number_suffixes.sort_by_key(|suffix| cmp::Reverse(suffix.len()));
+        Ok(Self {
+            name: file.name,
+            extensions: file.extensions,
+            first_lines,
+            hl_opts: HighlightingOptions {
+                numbers: file.numbers,
+                number_prefixes,
+                number_separator: file.number_separator,
+                number_exponents: file.number_exponents,
+                number_suffixes,
+                strings: file.strings,
+                multiline_strings: file.multiline_strings,
+                string_prefixes: file.string_prefixes,
+                raw_string_prefixes: file.raw_string_prefixes,
+                escape: file.escape,
+                character: file.character,
+                line_comment: file.line_comment,
+                doc_comments: file.doc_comments,
+                block_comment: file.block_comment,
+                nested_comments: file.nested_comments,
+                primary_keywords: file.primary_keywords.into_iter().collect(),
+                secondary_keywords: file.secondary_keywords.into_iter().collect(),
+                functions: file.functions,
+                function_keywords: file.function_keywords,
+                macros: file.macros,
+                lifetimes: file.lifetimes,
+                attributes: file.attributes,
+                type_names: file.type_names,
+            },
+        })
+    }
+    fn matches(&self, file_name: &str, first_line: Option<&str>) -> bool {
+        let extension = Path::new(file_name)
+            .extension()
+            .and_then(|extension| extension.to_str());
+        if let Some(extension) = extension {
+            if self.extensions.iter().any(|known| known == extension) {
+                return true;
+            }
+        }
+        first_line.map_or(false, |line| {
+            self.first_lines.iter().any(|pattern| pattern.is_match(line))
+        })
+    }
+}
+
+/// The known syntaxes, user definitions first so that they win over the
+/// built-in ones, and the problems found while loading them.
+struct Syntaxes {
+    syntaxes: Vec<Syntax>,
+    errors: Vec<String>,
+}
+
+static SYNTAXES: OnceLock<Syntaxes> = OnceLock::new();
+
+/// Loads the `*.toml` files in the `syntax` directory of the config
+/// directory, followed by the built-in definitions.
+fn syntaxes() -> &'static Syntaxes {
+    SYNTAXES.get_or_init(|| {
+        let mut syntaxes = Vec::new();
+        let mut errors = Vec::new();
+        let entries = config_dir().and_then(|dir| fs::read_dir(dir.join("syntax")).ok());
+        let mut paths: Vec<_> = entries
+            .into_iter()
+            .flatten()
+            .filter_map(Result::ok)
+            .map(|entry| entry.path())
+            .filter(|path| path.extension().map_or(false, |extension| extension == "toml"))
+            .collect();
+        paths.sort();
+        for path in paths {
+            match fs::read_to_string(&path)
+                .map_err(|err| err.to_string())
+                .and_then(|source| Syntax::from_toml(&source))
+            {
+                Ok(syntax) => syntaxes.push(syntax),
+                Err(error) => errors.push(format!("{}: {}", path.display(), error)),
+            }
+        }
+        for (file_name, source) in &BUILTIN_SYNTAXES {
+            match Syntax::from_toml(source) {
+                Ok(syntax) => syntaxes.push(syntax),
+                Err(error) => errors.push(format!("built-in syntax {}: {}", file_name, error)),
+            }
+        }
+        Syntaxes { syntaxes, errors }
+    })
+}
+
 pub struct FileType {
     name: String,
     hl_opts: HighlightingOptions,
 }
 
-#[derive(Default)]
+/// How to highlight a language, read from its syntax definition.
+#[derive(Default, Clone)]
 pub struct HighlightingOptions {
     numbers: bool,
-    strings: bool,
-    characters: bool,
-    comments: bool,
-    multiline_comments: bool,
-    primary_keywords: Vec<String>,
-    secondary_keywords: Vec<String>,
+    /// Prefixes of numbers in another radix, such as `0x`, with the radix
+    /// given by their last letter. They match regardless of case.
+    number_prefixes: Vec<(String, u32)>,
+    /// What may separate the digits of a number, such as `_` in `1_000`.
+    number_separator: Option<char>,
+    /// Whether decimal numbers may have an exponent, as in `1e-9`.
+    number_exponents: bool,
+    /// What may follow a number to give its type, such as `u64`, longest
+    /// first.
+    number_suffixes: Vec<String>,
+    /// Quotes that start and end a string on the same row.
+    strings: Vec<String>,
+    /// Quotes of strings that may span rows, such as `"""` in Python. They
+    /// are tried before `strings`.
+    multiline_strings: Vec<String>,
+    /// What may come right before the opening quote of a string as part of
+    /// it, such as `b` in `b"bytes"`.
+    string_prefixes: Vec<String>,
+    /// Prefixes of Rust-style raw strings, such as `r` in `r#"a "b" c"#`.
+    /// Raw strings have no escapes and end at a `"` followed by as many
+    /// `#` as they started with.
+    raw_string_prefixes: Vec<String>,
+    /// Makes the character after it part of a string or character literal,
+    /// even if it is a closing quote.
+    escape: Option<char>,
+    /// The quote around character literals.
+    character: Option<char>,
+    line_comment: Option<String>,
+    /// Starts of line comments that document code, such as `///`, but not
+    /// `////`.
+    doc_comments: Vec<String>,
+    block_comment: Option<(String, String)>,
+    /// Whether a block comment may contain other block comments.
+    nested_comments: bool,
+    /// Keywords are looked up by the whole word, see `Row::highlight`.
+    primary_keywords: HashSet<String>,
+    secondary_keywords: HashSet<String>,
+    /// Whether a name followed by `(` is highlighted as a function.
+    functions: bool,
+    /// Keywords that the name of a function definition follows, such as
+    /// `fn`.
+    function_keywords: Vec<String>,
+    /// Whether a name followed by `!` is highlighted as a macro, as in Rust.
+    macros: bool,
+    /// Whether Rust lifetimes such as `'a` are highlighted.
+    lifetimes: bool,
+    /// Whether Rust attributes such as `#[derive(Debug)]` are highlighted.
+    attributes: bool,
+    /// Whether CamelCase names are highlighted as types.
+    type_names: bool,
 }
 
 impl Default for FileType {
@@ -30,89 +284,21 @@ impl FileType {
     pub fn highlighting_options(&self) -> &HighlightingOptions {
         &self.hl_opts
     }
-    pub fn from(file_name: &str) -> Self {
-        if file_name.ends_with(".rs") {
-            return Self {
-                name: String::from("Rust"),
-                hl_opts: HighlightingOptions {
-                    numbers: true,
-                    strings: true,
-                    characters: true,
-                    comments: true,
-                    multiline_comments: true,
-                    primary_keywords: vec![
-                        "as".to_string(),
-                        "break".to_string(),
-                        "const".to_string(),
-                        "continue".to_string(),
-                        "crate".to_string(),
-                        "else".to_string(),
-                        "enum".to_string(),
-                        "extern".to_string(),
-                        "false".to_string(),
-                        "fn".to_string(),
-                        "for".to_string(),
-                        "if".to_string(),
-                        "impl".to_string(),
-                        "in".to_string(),
-                        "let".to_string(),
-                        "loop".to_string(),
-                        "match".to_string(),
-                        "mod".to_string(),
-                        "move".to_string(),
-                        "mut".to_string(),
-                        "pub".to_string(),
-                        "ref".to_string(),
-                        "return".to_string(),
-                        "self".to_string(),
-                        "Self".to_string(),
-                        "static".to_string(),
-                        "struct".to_string(),
-                        "super".to_string(),
-                        "trait".to_string(),
-                        "true".to_string(),
-                        "type".to_string(),
-                        "unsafe".to_string(),
-                        "use".to_string(),
-                        "where".to_string(),
-                        "while".to_string(),
-                        "dyn".to_string(),
-                        "abstract".to_string(),
-                        "become".to_string(),
-                        "box".to_string(),
-                        "do".to_string(),
-                        "final".to_string(),
-                        "macro".to_string(),
-                        "override".to_string(),
-                        "priv".to_string(),
-                        "typeof".to_string(),
-                        "unsized".to_string(),
-                        "virtual".to_string(),
-                        "yield".to_string(),
-                        "async".to_string(),
-                        "await".to_string(),
-                        "try".to_string(),
-                    ],
-                    secondary_keywords: vec![
-                        "bool".to_string(),
-                        "char".to_string(),
-                        "i8".to_string(),
-                        "i16".to_string(),
-                        "i32".to_string(),
-                        "i64".to_string(),
-                        "isize".to_string(),
-                        "u8".to_string(),
-                        "u16".to_string(),
-                        "u32".to_string(),
-                        "u64".to_string(),
-                        "usize".to_string(),
-                        "f32".to_string(),
-                        "f64".to_string(),
-                    ],
-                },
-            };
-        }
-        Self::default()
+    /// Picks the syntax for a file by its extension, or else by its first
+    /// line.
+    pub fn detect(file_name: &str, first_line: Option<&str>) -> Self {
+        syntaxes()
+            .syntaxes
+            .iter()
+            .find(|syntax| syntax.matches(file_name, first_line))
+            .map_or_else(Self::default, |syntax| Self {
+                name: syntax.name.clone(),
+                hl_opts: syntax.hl_opts.clone(),
+            })
+    }
+    /// Problems with the syntax definition files, built-in or the user's.
+    pub fn load_errors() -> &'static [String] {
+        &syntaxes().errors
     }
 }
 
@@ -120,22 +306,114 @@ impl HighlightingOptions {
     pub fn numbers(&self) -> bool {
         self.numbers
     }
-    pub fn strings(&self) -> bool {
-        self.strings
+    pub fn number_prefixes(&self) -> &[(String, u32)] {
+        &self.number_prefixes
+    }
+    pub fn number_separator(&self) -> Option<char> {
+        self.number_separator
+    }
+    pub fn number_exponents(&self) -> bool {
+        self.number_exponents
+    }
+    pub fn number_suffixes(&self) -> &[String] {
+        &self.number_suffixes
+    }
+    pub fn strings(&self) -> &[String] {
+        &self.strings
+    }
+    pub fn multiline_strings(&self) -> &[String] {
+        &self.multiline_strings
+    }
+    pub fn string_prefixes(&self) -> &[String] {
+        &self.string_prefixes
+    }
+    pub fn raw_string_prefixes(&self) -> &[String] {
+        &self.raw_string_prefixes
+    }
+    pub fn escape(&self) -> Option<char> {
+        self.escape
+    }
+    pub fn character(&self) -> Option<char> {
+        self.character
+    }
+    pub fn line_comment(&self) -> Option<&str> {
+        self.line_comment.as_deref()
     }
-    pub fn characters(&self) -> bool {
-        self.characters
+    pub fn doc_comments(&self) -> &[String] {
+        &self.doc_comments
     }
-    pub fn comments(&self) -> bool {
-        self.comments
+    pub fn nested_comments(&self) -> bool {
+        self.nested_comments
     }
-    pub fn primary_keywords(&self) -> &Vec<String> {
+    pub fn block_comment(&self) -> Option<(&str, &str)> {
+        self.block_comment
+            .as_ref()
+            .map(|(start, end)| (start.as_str(), end.as_str()))
+    }
+    pub fn primary_keywords(&self) -> &HashSet<String> {
         &self.primary_keywords
     }
-    pub fn secondary_keywords(&self) -> &Vec<String> {
+    pub fn secondary_keywords(&self) -> &HashSet<String> {
         &self.secondary_keywords
     }
-    pub fn multiline_comments(&self) -> bool {
-        self.multiline_comments
+    pub fn functions(&self) -> bool {
+        self.functions
+    }
+    pub fn function_keywords(&self) -> &[String] {
+        &self.function_keywords
+    }
+    pub fn macros(&self) -> bool {
+        self.macros
+    }
+    pub fn lifetimes(&self) -> bool {
+        self.lifetimes
+    }
+    pub fn attributes(&self) -> bool {
+        self.attributes
+    }
+    pub fn type_names(&self) -> bool {
+        self.type_names
+    }
+}
+
+#[cfg(test)]
+mod test_super {
+    use super::*;
+
+    #[test]
+    fn test_detect() {
+        for (_, source) in &BUILTIN_SYNTAXES {
+            assert!(Syntax::from_toml(source).is_ok());
+        }
+        assert!(!FileType::load_errors()
+            .iter()
+            .any(|error| error.starts_with("built-in")));
+        let rust = FileType::detect("src/main.rs", None);
+        assert_eq!(rust.name(), "Rust");
+        let opts = rust.highlighting_options();
+        assert_eq!(opts.block_comment(), Some(("/*", "*/")));
+        assert!(opts.primary_keywords().contains("fn"));
+        assert_eq!(FileType::detect("notes.txt", Some("fn main")).name(), "No filetype");
+        assert_eq!(FileType::detect("lib.hpp", None).name(), "C++");
+        assert_eq!(FileType::detect("app.tsx", None).name(), "TypeScript");
+        assert_eq!(FileType::detect("deploy", Some("#!/bin/bash")).name(), "Shell");
+        assert_eq!(FileType::detect("tool", Some("#!/usr/bin/env python3")).name(), "Python");
+        assert_eq!(FileType::detect("Cargo.toml", None).name(), "TOML");
+
+        let syntax = Syntax::from_toml(
+            r##"
+            name = "Shell"
+            first_lines = ["^#!.*\\b(ba|z)?sh\\b"]
+            line_comment = "#"
+            strings = ["\"", "'"]
+            "##,
+        )
+        .unwrap();
+        assert!(syntax.matches("build", Some("#!/usr/bin/env bash")));
+        assert!(!syntax.matches("build", Some("#!/usr/bin/python")));
+        assert_eq!(syntax.hl_opts.strings(), ["\"", "'"]);
+        assert!(Syntax::from_toml("name = \"X\"\nkeywords = []").is_err());
+        assert!(Syntax::from_toml("name = \"X\"\nline_comment = \"\"").is_err());
+        assert!(Syntax::from_toml("name = \"X\"\nnumber_prefixes = [\"0d\"]").is_err());
     }
 }
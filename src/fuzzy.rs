@@ -0,0 +1,62 @@
+/// Scores how well `pattern` matches `candidate` as a case-insensitive
+/// subsequence, or returns `None` if it does not match at all. Matches at
+/// word starts and runs of consecutive characters score higher.
+pub fn score(pattern: &str, candidate: &str) -> Option<usize> {
+    let pattern: Vec<char> = pattern.chars().flat_map(char::to_lowercase).collect();
+    let candidate: Vec<char> = candidate.chars().collect();
+    let lowered: Vec<char> = candidate
+        .iter()
+        .map(|c| c.to_lowercase().next().unwrap_or(*c))
+        .collect();
+    // best[k] is the best score for the rest of the pattern when its first
+    // character is matched at position k.
+    let mut best: Vec<Option<usize>> = vec![Some(0); candidate.len().saturating_add(1)];
+    for (pattern_index, wanted) in pattern.iter().enumerate().rev() {
+        let last = pattern_index.saturating_add(1) == pattern.len();
+        let mut next: Vec<Option<usize>> = vec![None; candidate.len().saturating_add(1)];
+        for (index, c) in lowered.iter().enumerate() {
+            if c != wanted {
+                continue;
+            }
+            let word_start = index == 0
+                || candidate
+                    .get(index.saturating_sub(1))
+                    .map_or(true, |previous| !previous.is_alphanumeric());
+            let own = if word_start { 3 } else { 1 };
+            let rest = if last {
+                Some(0)
+            } else {
+                best.iter()
+                    .enumerate()
+                    .skip(index.saturating_add(1))
+                    .filter_map(|(later, score)| {
+                        let consecutive = if later == index.saturating_add(1) { 3 } else { 0 };
+                        score.map(|score| score.saturating_add(consecutive))
+                    })
+                    .max()
+            };
+            next[index] = rest.map(|rest| rest.saturating_add(own));
+        }
+        best = next;
+    }
+    if pattern.is_empty() {
+        return Some(0);
+    }
+    best.into_iter().flatten().max()
+}
+
+#[cfg(test)]
+mod test_super {
+    use super::*;
+
+    #[test]
+    fn test_score() {
+        assert_eq!(score("", "save"), Some(0));
+        assert!(score("mwl", "move-word-left").is_some());
+        assert!(score("MWL", "move-word-left").is_some());
+        assert_eq!(score("lwm", "move-word-left"), None);
+        assert!(score("mwr", "move-word-right") > score("mwr", "move-word-left"));
+        assert!(score("save", "save-as") > score("save", "s-a-v-e"));
+        assert!(score("sa", "save-as") > score("sa", "page-up search"));
+    }
+}
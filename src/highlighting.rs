@@ -1,5 +1,4 @@
-use termion::color;
-#[derive(PartialEq, Clone, Copy, Debug)]
+#[derive(PartialEq, Eq, Hash, Clone, Copy, Debug)]
 pub enum Type {
     None,
     Number,
@@ -8,21 +7,81 @@ pub enum Type {
     Character,
     Comment,
     MultilineComment,
+    DocComment,
     PrimaryKeywords,
     SecondaryKeywords,
+    Function,
+    Macro,
+    Lifetime,
+    Attribute,
+    TypeName,
+    /// Tabs and spaces drawn with visible glyphs.
+    Whitespace,
+    TrailingWhitespace,
 }
 
 impl Type {
-    pub fn to_color(self) -> impl color::Color {
+    /// The name used for this type in theme files.
+    pub fn name(self) -> &'static str {
         match self {
-            Type::Number => color::Rgb(220, 163, 163),
-            Type::Match => color::Rgb(38, 139, 210),
-            Type::String => color::Rgb(211, 54, 130),
-            Type::Character => color::Rgb(108, 113, 196),
-            Type::Comment | Type::MultilineComment => color::Rgb(133, 153, 0),
-            Type::PrimaryKeywords => color::Rgb(181, 137, 0),
-            Type::SecondaryKeywords => color::Rgb(42, 161, 152),
-            _ => color::Rgb(255, 255, 255),
+            Type::None => "none",
+            Type::Number => "number",
+            Type::Match => "match",
+            Type::String => "string",
+            Type::Character => "character",
+            Type::Comment => "comment",
+            Type::MultilineComment => "multiline_comment",
+            Type::DocComment => "doc_comment",
+            Type::PrimaryKeywords => "primary_keywords",
+            Type::SecondaryKeywords => "secondary_keywords",
+            Type::Function => "function",
+            Type::Macro => "macro",
+            Type::Lifetime => "lifetime",
+            Type::Attribute => "attribute",
+            Type::TypeName => "type_name",
+            Type::Whitespace => "whitespace",
+            Type::TrailingWhitespace => "trailing_whitespace",
         }
     }
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "number" => Some(Type::Number),
+            "match" => Some(Type::Match),
+            "string" => Some(Type::String),
+            "character" => Some(Type::Character),
+            "comment" => Some(Type::Comment),
+            "multiline_comment" => Some(Type::MultilineComment),
+            "doc_comment" => Some(Type::DocComment),
+            "primary_keywords" => Some(Type::PrimaryKeywords),
+            "secondary_keywords" => Some(Type::SecondaryKeywords),
+            "function" => Some(Type::Function),
+            "macro" => Some(Type::Macro),
+            "lifetime" => Some(Type::Lifetime),
+            "attribute" => Some(Type::Attribute),
+            "type_name" => Some(Type::TypeName),
+            "whitespace" => Some(Type::Whitespace),
+            "trailing_whitespace" => Some(Type::TrailingWhitespace),
+            _ => None,
+        }
+    }
+}
+
+/// What a row ends inside of, which the next row starts in.
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum State {
+    Normal,
+    /// Inside this many block comments, which is more than one only where
+    /// block comments nest.
+    BlockComment(usize),
+    /// A string that spans rows, opened by the delimiter at this index of
+    /// `HighlightingOptions::multiline_strings`.
+    String(usize),
+    /// A raw string opened with this many `#`.
+    RawString(usize),
+}
+
+impl Default for State {
+    fn default() -> Self {
+        State::Normal
+    }
 }
@@ -0,0 +1,472 @@
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+use std::collections::BTreeMap;
+use std::fmt;
+
+/// The editor commands that can be bound to keys.
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum Command {
+    Quit,
+    Save,
+    SaveAs,
+    Find,
+    GotoLine,
+    SwitchTheme,
+    Help,
+    Palette,
+    CommandLine,
+    RecordMacro,
+    PlayMacro,
+    ToggleWhitespace,
+    MoveUp,
+    MoveDown,
+    MoveLeft,
+    MoveRight,
+    MoveWordLeft,
+    MoveWordRight,
+    PageUp,
+    PageDown,
+    LineStart,
+    LineEnd,
+    DocumentStart,
+    DocumentEnd,
+    InsertNewline,
+    InsertTab,
+    DeleteForward,
+    DeleteBackward,
+}
+
+impl Command {
+    pub const ALL: [Self; 28] = [
+        Self::Quit,
+        Self::Save,
+        Self::SaveAs,
+        Self::Find,
+        Self::GotoLine,
+        Self::SwitchTheme,
+        Self::Help,
+        Self::Palette,
+        Self::CommandLine,
+        Self::RecordMacro,
+        Self::PlayMacro,
+        Self::ToggleWhitespace,
+        Self::MoveUp,
+        Self::MoveDown,
+        Self::MoveLeft,
+        Self::MoveRight,
+        Self::MoveWordLeft,
+        Self::MoveWordRight,
+        Self::PageUp,
+        Self::PageDown,
+        Self::LineStart,
+        Self::LineEnd,
+        Self::DocumentStart,
+        Self::DocumentEnd,
+        Self::InsertNewline,
+        Self::InsertTab,
+        Self::DeleteForward,
+        Self::DeleteBackward,
+    ];
+
+    /// The name used for the command in the `[keybindings]` table.
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Quit => "quit",
+            Self::Save => "save",
+            Self::SaveAs => "save-as",
+            Self::Find => "find",
+            Self::GotoLine => "goto-line",
+            Self::SwitchTheme => "switch-theme",
+            Self::Help => "help",
+            Self::Palette => "command-palette",
+            Self::CommandLine => "command-line",
+            Self::RecordMacro => "record-macro",
+            Self::PlayMacro => "play-macro",
+            Self::ToggleWhitespace => "toggle-whitespace",
+            Self::MoveUp => "move-up",
+            Self::MoveDown => "move-down",
+            Self::MoveLeft => "move-left",
+            Self::MoveRight => "move-right",
+            Self::MoveWordLeft => "move-word-left",
+            Self::MoveWordRight => "move-word-right",
+            Self::PageUp => "page-up",
+            Self::PageDown => "page-down",
+            Self::LineStart => "line-start",
+            Self::LineEnd => "line-end",
+            Self::DocumentStart => "document-start",
+            Self::DocumentEnd => "document-end",
+            Self::InsertNewline => "insert-newline",
+            Self::InsertTab => "insert-tab",
+            Self::DeleteForward => "delete-forward",
+            Self::DeleteBackward => "delete-backward",
+        }
+    }
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.iter().copied().find(|command| command.name() == name)
+    }
+    pub fn description(self) -> &'static str {
+        match self {
+            Self::Quit => "Quit, asking again if the file is modified",
+            Self::Save => "Save the file",
+            Self::SaveAs => "Save the file under a new name",
+            Self::Find => "Search the file",
+            Self::GotoLine => "Jump to a line number",
+            Self::SwitchTheme => "Load another color theme",
+            Self::Help => "Show the active key bindings",
+            Self::Palette => "Pick a command by name",
+            Self::CommandLine => "Run a command like :w, :set or :s/a/b/",
+            Self::RecordMacro => "Start or stop recording a keyboard macro",
+            Self::PlayMacro => "Play a recorded keyboard macro",
+            Self::ToggleWhitespace => "Toggle visible whitespace",
+            Self::MoveUp => "Move up one line",
+            Self::MoveDown => "Move down one line",
+            Self::MoveLeft => "Move left one character",
+            Self::MoveRight => "Move right one character",
+            Self::MoveWordLeft => "Move to the previous word",
+            Self::MoveWordRight => "Move to the next word",
+            Self::PageUp => "Move up one screen",
+            Self::PageDown => "Move down one screen",
+            Self::LineStart => "Move to the start of the line",
+            Self::LineEnd => "Move to the end of the line",
+            Self::DocumentStart => "Move to the first line",
+            Self::DocumentEnd => "Move to the last line",
+            Self::InsertNewline => "Split the line at the cursor",
+            Self::InsertTab => "Insert a tab",
+            Self::DeleteForward => "Delete the character under the cursor",
+            Self::DeleteBackward => "Delete the character before the cursor",
+        }
+    }
+}
+
+/// One key press with its modifiers, like `Ctrl-K` or `Alt-Left`.
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub struct Key {
+    code: KeyCode,
+    modifiers: KeyModifiers,
+}
+
+impl Key {
+    /// Builds a key, folding Shift into the character for character keys so
+    /// that `Shift-a` and `A` are the same key.
+    pub fn new(code: KeyCode, modifiers: KeyModifiers) -> Self {
+        let modifiers = modifiers & (KeyModifiers::CONTROL | KeyModifiers::ALT | KeyModifiers::SHIFT);
+        match code {
+            KeyCode::Char(c) => {
+                let c = if modifiers.contains(KeyModifiers::SHIFT) {
+                    c.to_ascii_uppercase()
+                } else {
+                    c
+                };
+                Self {
+                    code: KeyCode::Char(c),
+                    modifiers: modifiers - KeyModifiers::SHIFT,
+                }
+            }
+            KeyCode::BackTab => Self {
+                code,
+                modifiers: modifiers | KeyModifiers::SHIFT,
+            },
+            _ => Self { code, modifiers },
+        }
+    }
+    pub fn code(self) -> KeyCode {
+        self.code
+    }
+    pub fn modifiers(self) -> KeyModifiers {
+        self.modifiers
+    }
+    /// Whether the key types its character rather than being a shortcut.
+    pub fn is_text(self) -> bool {
+        matches!(self.code, KeyCode::Char(_))
+            && !self.modifiers.intersects(KeyModifiers::CONTROL | KeyModifiers::ALT)
+    }
+    /// Parses a key written like `Ctrl-K`, `Alt-Shift-Left`, `F1` or `x`.
+    /// Letters combined with Ctrl or Alt are case-insensitive.
+    pub fn parse(text: &str) -> Option<Self> {
+        let (prefix, name) = match text.strip_suffix("--") {
+            Some(prefix) => (prefix, "-"),
+            None => match text.rsplit_once('-') {
+                Some((prefix, name)) if !name.is_empty() => (prefix, name),
+                _ => ("", text),
+            },
+        };
+        let mut modifiers = KeyModifiers::NONE;
+        for modifier in prefix.split('-').filter(|modifier| !modifier.is_empty()) {
+            modifiers |= match modifier.to_ascii_lowercase().as_str() {
+                "ctrl" | "c" => KeyModifiers::CONTROL,
+                "alt" | "a" | "meta" | "m" => KeyModifiers::ALT,
+                "shift" | "s" => KeyModifiers::SHIFT,
+                _ => return None,
+            };
+        }
+        let code = match name.to_ascii_lowercase().as_str() {
+            "enter" | "return" => KeyCode::Enter,
+            "esc" | "escape" => KeyCode::Esc,
+            "tab" => KeyCode::Tab,
+            "backtab" => KeyCode::BackTab,
+            "backspace" => KeyCode::Backspace,
+            "delete" | "del" => KeyCode::Delete,
+            "insert" | "ins" => KeyCode::Insert,
+            "home" => KeyCode::Home,
+            "end" => KeyCode::End,
+            "pageup" => KeyCode::PageUp,
+            "pagedown" => KeyCode::PageDown,
+            "up" => KeyCode::Up,
+            "down" => KeyCode::Down,
+            "left" => KeyCode::Left,
+            "right" => KeyCode::Right,
+            "space" => KeyCode::Char(' '),
+            lower => {
+                let mut chars = name.chars();
+                match (chars.next(), chars.next()) {
+                    (Some(c), None) => {
+                        if modifiers.intersects(KeyModifiers::CONTROL | KeyModifiers::ALT) {
+                            KeyCode::Char(c.to_ascii_lowercase())
+                        } else {
+                            KeyCode::Char(c)
+                        }
+                    }
+                    _ => {
+                        let number = lower.strip_prefix('f')?.parse::<u8>().ok()?;
+                        if !(1..=24).contains(&number) {
+                            return None;
+                        }
+                        KeyCode::F(number)
+                    }
+                }
+            }
+        };
+        Some(Self::new(code, modifiers))
+    }
+}
+
+impl From<KeyEvent> for Key {
+    fn from(event: KeyEvent) -> Self {
+        Self::new(event.code, event.modifiers)
+    }
+}
+
+impl fmt::Display for Key {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.modifiers.contains(KeyModifiers::CONTROL) {
+            f.write_str("Ctrl-")?;
+        }
+        if self.modifiers.contains(KeyModifiers::ALT) {
+            f.write_str("Alt-")?;
+        }
+        if self.modifiers.contains(KeyModifiers::SHIFT) && self.code != KeyCode::BackTab {
+            f.write_str("Shift-")?;
+        }
+        match self.code {
+            KeyCode::Char(' ') => f.write_str("Space"),
+            KeyCode::Char(c) if self.is_text() => write!(f, "{}", c),
+            KeyCode::Char(c) => write!(f, "{}", c.to_ascii_uppercase()),
+            KeyCode::F(number) => write!(f, "F{}", number),
+            KeyCode::BackTab => f.write_str("BackTab"),
+            KeyCode::Backspace => f.write_str("Backspace"),
+            KeyCode::Enter => f.write_str("Enter"),
+            KeyCode::Left => f.write_str("Left"),
+            KeyCode::Right => f.write_str("Right"),
+            KeyCode::Up => f.write_str("Up"),
+            KeyCode::Down => f.write_str("Down"),
+            KeyCode::Home => f.write_str("Home"),
+            KeyCode::End => f.write_str("End"),
+            KeyCode::PageUp => f.write_str("PageUp"),
+            KeyCode::PageDown => f.write_str("PageDown"),
+            KeyCode::Tab => f.write_str("Tab"),
+            KeyCode::Delete => f.write_str("Delete"),
+            KeyCode::Insert => f.write_str("Insert"),
+            KeyCode::Esc => f.write_str("Esc"),
+            other => write!(f, "{:?}", other),
+        }
+    }
+}
+
+/// Parses a chord of space separated keys, like `Ctrl-K Ctrl-S`.
+pub fn parse_chord(text: &str) -> Option<Vec<Key>> {
+    let keys: Option<Vec<Key>> = text.split_whitespace().map(Key::parse).collect();
+    keys.filter(|keys| !keys.is_empty())
+}
+
+/// Formats a chord the way `parse_chord` reads it.
+pub fn format_chord(keys: &[Key]) -> String {
+    keys.iter()
+        .map(ToString::to_string)
+        .collect::<Vec<String>>()
+        .join(" ")
+}
+
+/// What the keys pressed so far mean.
+#[derive(PartialEq, Debug)]
+pub enum Lookup {
+    Command(Command),
+    /// The keys start a longer chord; wait for the next key.
+    Prefix,
+    Unbound,
+}
+
+const DEFAULT_BINDINGS: [(&str, Command); 35] = [
+    ("Ctrl-Q", Command::Quit),
+    ("Ctrl-S", Command::Save),
+    ("Ctrl-K Ctrl-S", Command::SaveAs),
+    ("Ctrl-F", Command::Find),
+    ("Ctrl-G", Command::GotoLine),
+    ("Ctrl-T", Command::SwitchTheme),
+    ("F1", Command::Help),
+    ("Ctrl-K Ctrl-H", Command::Help),
+    ("Ctrl-P", Command::Palette),
+    ("Ctrl-K :", Command::CommandLine),
+    ("Ctrl-K Ctrl-R", Command::RecordMacro),
+    ("Ctrl-K Ctrl-P", Command::PlayMacro),
+    ("Ctrl-K Ctrl-W", Command::ToggleWhitespace),
+    ("Up", Command::MoveUp),
+    ("Down", Command::MoveDown),
+    ("Left", Command::MoveLeft),
+    ("Right", Command::MoveRight),
+    ("Ctrl-Left", Command::MoveWordLeft),
+    ("Alt-B", Command::MoveWordLeft),
+    ("Ctrl-Right", Command::MoveWordRight),
+    ("Alt-F", Command::MoveWordRight),
+    ("PageUp", Command::PageUp),
+    ("PageDown", Command::PageDown),
+    ("Home", Command::LineStart),
+    ("Ctrl-A", Command::LineStart),
+    ("End", Command::LineEnd),
+    ("Ctrl-E", Command::LineEnd),
+    ("Ctrl-Home", Command::DocumentStart),
+    ("Ctrl-End", Command::DocumentEnd),
+    ("Enter", Command::InsertNewline),
+    ("Tab", Command::InsertTab),
+    ("Delete", Command::DeleteForward),
+    ("Ctrl-D", Command::DeleteForward),
+    ("Backspace", Command::DeleteBackward),
+    ("Ctrl-H", Command::DeleteBackward),
+];
+
+/// Maps key chords to commands.
+#[derive(Clone)]
+pub struct Keymap {
+    bindings: Vec<(Vec<Key>, Command)>,
+}
+
+impl Default for Keymap {
+    fn default() -> Self {
+        let mut keymap = Self {
+            bindings: Vec::new(),
+        };
+        for (chord, command) in &DEFAULT_BINDINGS {
+            if let Some(keys) = parse_chord(chord) {
+                keymap.bind(keys, *command);
+            }
+        }
+        keymap
+    }
+}
+
+impl Keymap {
+    /// The default bindings with the `[keybindings]` table of the config
+    /// applied on top. Binding a chord to `none` removes it. Invalid entries
+    /// are skipped and reported.
+    pub fn from_config(keybindings: &BTreeMap<String, String>) -> (Self, Vec<String>) {
+        let mut keymap = Self::default();
+        let mut errors = Vec::new();
+        for (chord, name) in keybindings {
+            let keys = match parse_chord(chord) {
+                Some(keys) => keys,
+                None => {
+                    errors.push(format!("invalid key binding `{}`", chord));
+                    continue;
+                }
+            };
+            if name == "none" {
+                keymap.unbind(&keys);
+            } else if let Some(command) = Command::from_name(name) {
+                keymap.bind(keys, command);
+            } else {
+                errors.push(format!("unknown command `{}` for `{}`", name, chord));
+            }
+        }
+        (keymap, errors)
+    }
+    /// Binds `keys` to `command`, replacing bindings that the new chord
+    /// would shadow or be shadowed by.
+    pub fn bind(&mut self, keys: Vec<Key>, command: Command) {
+        self.bindings
+            .retain(|(bound, _)| !bound.starts_with(&keys) && !keys.starts_with(bound));
+        self.bindings.push((keys, command));
+    }
+    pub fn unbind(&mut self, keys: &[Key]) {
+        self.bindings.retain(|(bound, _)| bound != keys);
+    }
+    pub fn lookup(&self, keys: &[Key]) -> Lookup {
+        let mut result = Lookup::Unbound;
+        for (bound, command) in &self.bindings {
+            if bound == keys {
+                return Lookup::Command(*command);
+            }
+            if bound.starts_with(keys) {
+                result = Lookup::Prefix;
+            }
+        }
+        result
+    }
+    /// The chords bound to `command`, in the order they were bound.
+    pub fn chords_for(&self, command: Command) -> Vec<String> {
+        self.bindings
+            .iter()
+            .filter(|(_, bound)| *bound == command)
+            .map(|(keys, _)| format_chord(keys))
+            .collect()
+    }
+    /// The first chord bound to `command`, for use in messages.
+    pub fn chord_for(&self, command: Command) -> Option<String> {
+        self.chords_for(command).into_iter().next()
+    }
+}
+
+#[cfg(test)]
+mod test_super {
+    use super::*;
+
+    #[test]
+    fn test_parse_key() {
+        let ctrl_k = Key::new(KeyCode::Char('k'), KeyModifiers::CONTROL);
+        assert_eq!(Key::parse("Ctrl-K"), Some(ctrl_k));
+        assert_eq!(Key::parse("ctrl-k"), Some(ctrl_k));
+        assert_eq!(Key::parse("Shift-a"), Key::parse("A"));
+        assert_eq!(
+            Key::from(KeyEvent::new(KeyCode::Char('A'), KeyModifiers::SHIFT)),
+            Key::new(KeyCode::Char('A'), KeyModifiers::NONE)
+        );
+        assert_eq!(Key::parse("F12"), Some(Key::new(KeyCode::F(12), KeyModifiers::NONE)));
+        assert_eq!(Key::parse("Ctrl--"), Some(Key::new(KeyCode::Char('-'), KeyModifiers::CONTROL)));
+        assert_eq!(Key::parse("Hyper-K"), None);
+        assert_eq!(Key::parse("F99"), None);
+        assert_eq!(Key::parse("PgUp"), None);
+        for text in ["Ctrl-K", "Alt-Shift-Left", "F1", "x", "Space", "BackTab"] {
+            assert_eq!(Key::parse(text).unwrap().to_string(), text);
+        }
+    }
+
+    #[test]
+    fn test_lookup() {
+        let mut keybindings = BTreeMap::new();
+        keybindings.insert(String::from("Ctrl-K Ctrl-Q"), String::from("quit"));
+        keybindings.insert(String::from("Ctrl-Q"), String::from("none"));
+        keybindings.insert(String::from("Ctrl-X"), String::from("explode"));
+        keybindings.insert(String::from("Ctrl-"), String::from("save"));
+        let (keymap, errors) = Keymap::from_config(&keybindings);
+        assert_eq!(errors.len(), 2);
+
+        let chord = parse_chord("Ctrl-K Ctrl-Q").unwrap();
+        assert_eq!(keymap.lookup(&chord[..1]), Lookup::Prefix);
+        assert_eq!(keymap.lookup(&chord), Lookup::Command(Command::Quit));
+        assert_eq!(keymap.lookup(&parse_chord("Ctrl-Q").unwrap()), Lookup::Unbound);
+        assert_eq!(keymap.lookup(&parse_chord("Ctrl-S").unwrap()), Lookup::Command(Command::Save));
+        assert_eq!(keymap.chord_for(Command::Quit), Some(String::from("Ctrl-K Ctrl-Q")));
+
+        let mut keymap = Keymap::default();
+        keymap.bind(parse_chord("Ctrl-K").unwrap(), Command::Find);
+        assert_eq!(keymap.lookup(&chord[..1]), Lookup::Command(Command::Find));
+        assert!(keymap.chords_for(Command::SaveAs).is_empty());
+    }
+}
@@ -0,0 +1,53 @@
+#![warn(clippy::all, clippy::pedantic, clippy::restriction)]
+#![allow(
+clippy::missing_docs_in_private_items,
+clippy::implicit_return,
+clippy::shadow_reuse,
+clippy::print_stdout,
+clippy::wildcard_enum_match_arm,
+clippy::else_if_without_else
+)]
+
+mod backend;
+mod color;
+mod config;
+mod document;
+mod editor;
+mod ex;
+mod filetype;
+mod fuzzy;
+mod highlighting;
+mod keymap;
+mod macros;
+mod palette;
+mod prompt;
+mod row;
+mod terminal;
+mod theme;
+#[cfg(feature = "tree-sitter")]
+mod treesitter;
+mod vim;
+mod logger;
+
+pub use crate::logger::init_log;
+
+pub use backend::Backend;
+pub use backend::CrosstermBackend;
+pub use backend::TestBackend;
+pub use color::ColorSupport;
+pub use config::Config;
+pub use document::Document;
+pub use editor::Editor;
+pub use editor::Position;
+pub use editor::SearchDirection;
+pub use filetype::FileType;
+pub use filetype::HighlightingOptions;
+pub use keymap::Command;
+pub use keymap::Key;
+pub use keymap::Keymap;
+pub use row::Row;
+pub use row::Whitespace;
+pub use terminal::Cell;
+pub use terminal::Style;
+pub use terminal::Terminal;
+pub use theme::Theme;
@@ -8,21 +8,30 @@ use log4rs::Config;
 use log4rs::config::{Appender, Root};
 use log4rs::encode::pattern::PatternEncoder;
 use log::LevelFilter;
+use std::path::Path;
 
-pub fn init_log() {
+pub fn init_log(log_file: &str) {
     let window_size = 3; // log0, log1, log2
-    let fixed_window_roller = FixedWindowRoller::builder().build("log/app-{}.log", window_size).unwrap();
+    let path = Path::new(log_file);
+    let roll_pattern = path.with_file_name(format!(
+        "{}-{{}}.{}",
+        path.file_stem().and_then(|stem| stem.to_str()).unwrap_or("app"),
+        path.extension().and_then(|ext| ext.to_str()).unwrap_or("log")
+    ));
+    let fixed_window_roller = FixedWindowRoller::builder()
+        .build(&roll_pattern.to_string_lossy(), window_size)
+        .unwrap();
     let size_limit = 100 * 1024 * 1024; // 100M as max log file size to roll
     let size_trigger = SizeTrigger::new(size_limit);
     let compound_policy = CompoundPolicy::new(Box::new(size_trigger), Box::new(fixed_window_roller));
     let rolling_file = RollingFileAppender::builder()
         .encoder(Box::new(PatternEncoder::new("{d} - {m}{n}")))
-        .build("log/app.log", Box::new(compound_policy)).unwrap();
+        .build(log_file, Box::new(compound_policy)).unwrap();
 
 
     let file_out = FileAppender::builder()
         .encoder(Box::new(PatternEncoder::new("{d} - {m}{n}")))
-        .build("log/app.log")
+        .build(log_file)
         .unwrap();
     let stdout = ConsoleAppender::builder().build();
 
@@ -0,0 +1,165 @@
+use crate::keymap::{format_chord, parse_chord, Key};
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+
+/// Name of the file in the config directory that keeps recorded macros.
+pub const MACROS_FILE_NAME: &str = "macros.toml";
+
+/// Keyboard macros: key sequences recorded into registers named by a
+/// letter or digit.
+#[derive(Default)]
+pub struct Macros {
+    registers: BTreeMap<char, Vec<Key>>,
+    recording: Option<(char, Vec<Key>)>,
+    /// Length of the recording when the chord being typed started.
+    chord_start: usize,
+    last_played: Option<char>,
+    /// Where the macros are saved after each recording.
+    path: Option<PathBuf>,
+}
+
+impl Macros {
+    /// Loads the macros saved at `path`, where later recordings are saved
+    /// too. A missing file is not an error.
+    pub fn load(path: PathBuf) -> (Self, Vec<String>) {
+        let mut macros = Self::default();
+        let mut errors = Vec::new();
+        if path.is_file() {
+            match fs::read_to_string(&path) {
+                Ok(source) => {
+                    for error in macros.merge_toml(&source) {
+                        errors.push(format!("{}: {}", path.display(), error));
+                    }
+                }
+                Err(error) => errors.push(format!("{}: {}", path.display(), error)),
+            }
+        }
+        macros.path = Some(path);
+        (macros, errors)
+    }
+    /// Adds the macros in `source`, a table of register names to chords.
+    /// Invalid entries are skipped and reported.
+    pub fn merge_toml(&mut self, source: &str) -> Vec<String> {
+        let table: BTreeMap<String, String> = match toml::from_str(source) {
+            Ok(table) => table,
+            Err(error) => return vec![error.to_string()],
+        };
+        let mut errors = Vec::new();
+        for (name, keys) in table {
+            let mut chars = name.chars();
+            match (chars.next(), chars.next(), parse_chord(&keys)) {
+                (Some(register), None, Some(keys)) if is_register(register) => {
+                    self.registers.insert(register, keys);
+                }
+                (Some(register), None, _) if is_register(register) => {
+                    errors.push(format!("invalid keys for macro `{}`", name));
+                }
+                _ => errors.push(format!("invalid macro register `{}`", name)),
+            }
+        }
+        errors
+    }
+    pub fn to_toml(&self) -> String {
+        let table: BTreeMap<String, String> = self
+            .registers
+            .iter()
+            .map(|(register, keys)| (register.to_string(), format_chord(keys)))
+            .collect();
+        toml::to_string(&table).unwrap_or_default()
+    }
+    /// The register being recorded into.
+    pub fn recording(&self) -> Option<char> {
+        self.recording.as_ref().map(|(register, _)| *register)
+    }
+    pub fn start_recording(&mut self, register: char) {
+        self.recording = Some((register, Vec::new()));
+        self.chord_start = 0;
+    }
+    /// Marks the start of a new key chord, so that the chord which stops
+    /// the recording is left out of it.
+    pub fn start_chord(&mut self) {
+        if let Some((_, keys)) = &self.recording {
+            self.chord_start = keys.len();
+        }
+    }
+    pub fn record(&mut self, key: Key) {
+        // Keys without a name could not be saved and loaded again.
+        if let Some((_, keys)) = &mut self.recording {
+            if Key::parse(&key.to_string()) == Some(key) {
+                keys.push(key);
+            }
+        }
+    }
+    /// Stores the recorded keys in their register and saves all macros.
+    /// Returns the register, or `None` if nothing was being recorded.
+    pub fn stop_recording(&mut self) -> Result<Option<char>, io::Error> {
+        let (register, mut keys) = match self.recording.take() {
+            Some(recording) => recording,
+            None => return Ok(None),
+        };
+        keys.truncate(self.chord_start);
+        if keys.is_empty() {
+            self.registers.remove(&register);
+        } else {
+            self.registers.insert(register, keys);
+        }
+        self.save()?;
+        Ok(Some(register))
+    }
+    /// The keys of the macro in `register` to play; `@` stands for the
+    /// macro played last.
+    pub fn play(&mut self, register: char) -> Option<Vec<Key>> {
+        let register = if register == '@' { self.last_played? } else { register };
+        let keys = self.registers.get(&register)?.clone();
+        self.last_played = Some(register);
+        Some(keys)
+    }
+    fn save(&self) -> Result<(), io::Error> {
+        let path = match &self.path {
+            Some(path) => path,
+            None => return Ok(()),
+        };
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        fs::write(path, self.to_toml())
+    }
+}
+
+/// Whether `c` can name a macro register.
+pub fn is_register(c: char) -> bool {
+    c.is_ascii_alphanumeric()
+}
+
+#[cfg(test)]
+mod test_super {
+    use super::*;
+    use crossterm::event::{KeyCode, KeyModifiers};
+
+    #[test]
+    fn test_record_and_play() {
+        let mut macros = Macros::default();
+        macros.start_recording('a');
+        for key in parse_chord("x Space - Ctrl-- Enter Ctrl-K").unwrap() {
+            macros.start_chord();
+            macros.record(key);
+        }
+        macros.record(Key::new(KeyCode::Char('r'), KeyModifiers::CONTROL));
+        macros.record(Key::new(KeyCode::Null, KeyModifiers::NONE));
+        assert_eq!(macros.recording(), Some('a'));
+        assert_eq!(macros.stop_recording().unwrap(), Some('a'));
+        assert_eq!(macros.recording(), None);
+        assert_eq!(macros.play('@'), None);
+        assert_eq!(macros.play('a'), parse_chord("x Space - Ctrl-- Enter"));
+        assert_eq!(macros.play('@'), parse_chord("x Space - Ctrl-- Enter"));
+
+        let mut loaded = Macros::default();
+        assert!(loaded.merge_toml(&macros.to_toml()).is_empty());
+        assert_eq!(loaded.play('a'), parse_chord("x Space - Ctrl-- Enter"));
+        let errors = loaded.merge_toml("ab = \"x\"\nb = \"Hyper-K\"\nc = \"y\"");
+        assert_eq!(errors.len(), 2);
+        assert_eq!(loaded.play('c'), parse_chord("y"));
+    }
+}
@@ -1,35 +1,15 @@
 #![warn(clippy::all, clippy::pedantic, clippy::restriction)]
 #![allow(
 clippy::missing_docs_in_private_items,
-clippy::implicit_return,
-clippy::shadow_reuse,
-clippy::print_stdout,
-clippy::wildcard_enum_match_arm,
-clippy::else_if_without_else
+clippy::implicit_return
 )]
 
-mod document;
-mod editor;
-mod filetype;
-mod highlighting;
-mod row;
-mod terminal;
-mod logger;
-
-use crate::logger::init_log;
-
-pub use document::Document;
-use editor::Editor;
-pub use editor::Position;
-pub use editor::SearchDirection;
-pub use filetype::FileType;
-pub use filetype::HighlightingOptions;
-pub use row::Row;
-pub use terminal::Terminal;
-use crossterm::terminal::enable_raw_mode;
+use hecto::init_log;
+use hecto::Config;
+use hecto::Editor;
 
 fn main() {
-    init_log();
-    enable_raw_mode();
-    Editor::default().run();
+    let (config, config_errors) = Config::load();
+    init_log(&config.log_file);
+    Editor::default(config, &config_errors).run();
 }
@@ -0,0 +1,78 @@
+use crate::fuzzy;
+use crate::keymap::Command;
+use std::cmp;
+
+/// The commands listed by the command palette, filtered by the text typed
+/// into its prompt.
+pub struct Palette {
+    matches: Vec<Command>,
+    selected: usize,
+}
+
+impl Default for Palette {
+    fn default() -> Self {
+        let mut palette = Self {
+            matches: Vec::new(),
+            selected: 0,
+        };
+        palette.filter("");
+        palette
+    }
+}
+
+impl Palette {
+    /// Keeps the commands whose name or description fuzzily match `query`,
+    /// best match first, and selects the first one.
+    pub fn filter(&mut self, query: &str) {
+        let mut scored: Vec<(Command, usize)> = Command::ALL
+            .iter()
+            .filter(|command| **command != Command::Palette)
+            .filter_map(|command| {
+                let name = fuzzy::score(query, command.name());
+                let description = fuzzy::score(query, command.description());
+                cmp::max(name, description).map(|score| (*command, score))
+            })
+            .collect();
+        scored.sort_by(|a, b| b.1.cmp(&a.1));
+        self.matches = scored.into_iter().map(|(command, _)| command).collect();
+        self.selected = 0;
+    }
+    pub fn matches(&self) -> &[Command] {
+        &self.matches
+    }
+    pub fn selected_index(&self) -> usize {
+        self.selected
+    }
+    pub fn selected(&self) -> Option<Command> {
+        self.matches.get(self.selected).copied()
+    }
+    pub fn select_previous(&mut self) {
+        self.selected = self.selected.saturating_sub(1);
+    }
+    pub fn select_next(&mut self) {
+        if self.selected.saturating_add(1) < self.matches.len() {
+            self.selected = self.selected.saturating_add(1);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test_super {
+    use super::*;
+
+    #[test]
+    fn test_filter() {
+        let mut palette = Palette::default();
+        assert_eq!(palette.matches().len(), Command::ALL.len() - 1);
+        palette.filter("mwr");
+        assert_eq!(palette.selected(), Some(Command::MoveWordRight));
+        palette.select_previous();
+        assert_eq!(palette.selected_index(), 0);
+        palette.filter("theme");
+        assert_eq!(palette.selected(), Some(Command::SwitchTheme));
+        palette.filter("qqqq");
+        assert_eq!(palette.selected(), None);
+        palette.select_next();
+        assert_eq!(palette.selected_index(), 0);
+    }
+}
@@ -0,0 +1,132 @@
+use unicode_segmentation::UnicodeSegmentation;
+use unicode_width::UnicodeWidthStr;
+
+/// The prompts that remember what was entered into them.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum History {
+    Search,
+    SaveAs,
+    GotoLine,
+    CommandLine,
+}
+
+/// The line typed into a prompt, with a cursor counted in graphemes.
+#[derive(Default)]
+pub struct Input {
+    text: String,
+    cursor: usize,
+}
+
+impl Input {
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+    /// The display width of the text before the cursor.
+    pub fn cursor_column(&self) -> usize {
+        self.text[..self.byte_index(self.cursor)].width()
+    }
+    /// Replaces the text and puts the cursor at its end.
+    pub fn set_text(&mut self, text: String) {
+        self.cursor = text.graphemes(true).count();
+        self.text = text;
+    }
+    pub fn insert(&mut self, c: char) {
+        let mut buffer = [0; 4];
+        self.insert_str(c.encode_utf8(&mut buffer));
+    }
+    pub fn insert_str(&mut self, text: &str) {
+        let at = self.byte_index(self.cursor);
+        self.text.insert_str(at, text);
+        // Combining characters may join the grapheme before the cursor.
+        self.cursor = self.text[..at.saturating_add(text.len())]
+            .graphemes(true)
+            .count();
+    }
+    pub fn delete_backward(&mut self) {
+        if self.cursor > 0 {
+            self.cursor = self.cursor.saturating_sub(1);
+            self.delete_forward();
+        }
+    }
+    pub fn delete_forward(&mut self) {
+        let start = self.byte_index(self.cursor);
+        let end = self.byte_index(self.cursor.saturating_add(1));
+        self.text.replace_range(start..end, "");
+    }
+    /// Deletes the whitespace-separated word before the cursor, like
+    /// Ctrl-W in a shell.
+    pub fn delete_word_backward(&mut self) {
+        let graphemes: Vec<&str> = self.text.graphemes(true).take(self.cursor).collect();
+        let is_blank = |index: usize| graphemes[index].chars().all(char::is_whitespace);
+        let mut start = self.cursor;
+        while start > 0 && is_blank(start.saturating_sub(1)) {
+            start = start.saturating_sub(1);
+        }
+        while start > 0 && !is_blank(start.saturating_sub(1)) {
+            start = start.saturating_sub(1);
+        }
+        let (from, to) = (self.byte_index(start), self.byte_index(self.cursor));
+        self.text.replace_range(from..to, "");
+        self.cursor = start;
+    }
+    pub fn move_left(&mut self) {
+        self.cursor = self.cursor.saturating_sub(1);
+    }
+    pub fn move_right(&mut self) {
+        if self.cursor < self.len() {
+            self.cursor = self.cursor.saturating_add(1);
+        }
+    }
+    pub fn move_home(&mut self) {
+        self.cursor = 0;
+    }
+    pub fn move_end(&mut self) {
+        self.cursor = self.len();
+    }
+    fn len(&self) -> usize {
+        self.text.graphemes(true).count()
+    }
+    fn byte_index(&self, index: usize) -> usize {
+        self.text
+            .grapheme_indices(true)
+            .nth(index)
+            .map_or(self.text.len(), |(byte_index, _)| byte_index)
+    }
+}
+
+#[cfg(test)]
+mod test_super {
+    use super::*;
+
+    #[test]
+    fn test_editing() {
+        let mut input = Input::default();
+        input.insert_str("中文 src/main.rs");
+        input.move_home();
+        input.move_right();
+        input.delete_backward();
+        assert_eq!(input.text(), "文 src/main.rs");
+        assert_eq!(input.cursor, 0);
+        input.insert('e');
+        input.insert('\u{301}');
+        assert_eq!(input.text(), "e\u{301}文 src/main.rs");
+        assert_eq!(input.cursor, 1);
+        input.move_right();
+        assert_eq!(input.cursor_column(), 3);
+
+        input.move_end();
+        input.insert(' ');
+        input.delete_word_backward();
+        assert_eq!(input.text(), "e\u{301}文 ");
+        input.delete_word_backward();
+        assert_eq!(input.text(), "");
+        input.delete_backward();
+        assert_eq!(input.cursor, 0);
+
+        input.set_text(String::from("abc"));
+        assert_eq!(input.cursor, 3);
+        input.move_home();
+        input.delete_forward();
+        assert_eq!(input.text(), "bc");
+    }
+}
@@ -1,76 +1,174 @@
 use crate::highlighting;
 use crate::HighlightingOptions;
 use crate::SearchDirection;
+use std::borrow::Cow;
 use std::cmp;
-use termion::color;
+use std::collections::HashSet;
+use std::iter;
 use unicode_segmentation::UnicodeSegmentation;
-use unicode_width::{UnicodeWidthChar, UnicodeWidthStr};
+use unicode_width::UnicodeWidthStr;
 
+/// Display width of a tab stop in rows that were not given another one.
+pub const DEFAULT_TAB_WIDTH: usize = 4;
+
+/// How `Row::render` shows whitespace.
+#[derive(Clone, Copy, Default, PartialEq, Debug)]
+pub struct Whitespace {
+    /// Draw tabs, trailing spaces, non-breaking spaces and full-width spaces
+    /// with glyphs.
+    pub visible: bool,
+    /// Highlight trailing whitespace even when it is not visible.
+    pub trailing: bool,
+}
 
-#[derive(Default)]
 pub struct Row {
     pub string: String,
     highlighting: Vec<highlighting::Type>,
     pub is_highlighted: bool,
+    /// What the row started and ended inside of, as of the last
+    /// highlighting.
+    start_state: highlighting::State,
+    end_state: highlighting::State,
     len: usize,
     width: usize,
+    tab_width: usize,
     pub word_width_index: Vec<usize>,
 }
 
+impl Default for Row {
+    fn default() -> Self {
+        Self::from("")
+    }
+}
+
 impl From<&str> for Row {
     fn from(slice: &str) -> Self {
-        let mut word_index = vec![];
-        let char_array: Vec<char> = slice.chars().collect();
+        Self::new(slice, DEFAULT_TAB_WIDTH)
+    }
+}
 
-        for str in char_array {
-            word_index.push(unicode_width::UnicodeWidthChar::width(str).unwrap_or(0));
-        }
-        Self {
+impl Row {
+    pub fn new(slice: &str, tab_width: usize) -> Self {
+        let mut row = Self {
             string: String::from(slice),
             highlighting: Vec::new(),
             is_highlighted: false,
-            len: slice.graphemes(true).count(),
-            width: String::from(slice).width(),
-            word_width_index: word_index,
+            start_state: highlighting::State::Normal,
+            end_state: highlighting::State::Normal,
+            len: 0,
+            width: 0,
+            tab_width: cmp::max(tab_width, 1),
+            word_width_index: Vec::new(),
+        };
+        row.update_widths();
+        row
+    }
+    pub fn tab_width(&self) -> usize {
+        self.tab_width
+    }
+    /// Changes the tab stop width and recomputes the column layout.
+    pub fn set_tab_width(&mut self, tab_width: usize) {
+        self.tab_width = cmp::max(tab_width, 1);
+        self.update_widths();
+    }
+    /// Recomputes the length and the display width of every grapheme, with
+    /// tabs extending to the next tab stop.
+    #[allow(clippy::integer_arithmetic)]
+    fn update_widths(&mut self) {
+        let tab_width = self.tab_width;
+        self.word_width_index.clear();
+        let mut column = 0;
+        for grapheme in self.string[..].graphemes(true) {
+            let width = if grapheme == "\t" {
+                tab_width - column % tab_width
+            } else {
+                grapheme.width()
+            };
+            self.word_width_index.push(width);
+            column += width;
         }
+        self.len = self.word_width_index.len();
+        self.width = column;
     }
-}
-
-impl Row {
-    pub fn render(&self, start: usize, end: usize) -> String {
-        let end = cmp::min(end, self.string.len());
-        let start = cmp::min(start, end);
-        let mut result = String::new();
-        let mut current_highlighting = &highlighting::Type::None;
-        #[allow(clippy::integer_arithmetic)]
-        for (index, grapheme) in self.string[..]
-            .graphemes(true)
-            .enumerate()
-            .skip(start)
-            .take(end - start)
-        {
-            if let Some(c) = grapheme.chars().next() {
-                let highlighting_type = self
+    /// Returns the display column at which the grapheme at `index` starts.
+    pub fn column_of(&self, index: usize) -> usize {
+        self.word_width_index.iter().take(index).sum()
+    }
+    /// Returns the visible graphemes between the display columns `start` and
+    /// `end`, grouped into runs of the same highlighting type. Tabs and wide
+    /// graphemes cut off by the edges are rendered as spaces, unless
+    /// `whitespace` asks for whitespace to be shown.
+    #[allow(clippy::integer_arithmetic)]
+    pub fn render(&self, start: usize, end: usize, whitespace: Whitespace) -> Vec<(String, highlighting::Type)> {
+        let mut result: Vec<(String, highlighting::Type)> = Vec::new();
+        let trailing_start = if whitespace.visible || whitespace.trailing {
+            self.string.trim_end().graphemes(true).count()
+        } else {
+            self.len
+        };
+        let mut column = 0;
+        for (index, grapheme) in self.string[..].graphemes(true).enumerate() {
+            let width = self.word_width_index.get(index).copied().unwrap_or(0);
+            let grapheme_start = column;
+            column += width;
+            if column <= start || width == 0 {
+                continue;
+            }
+            if grapheme_start >= end {
+                break;
+            }
+            let visible = cmp::min(column, end) - cmp::max(grapheme_start, start);
+            let trailing = index >= trailing_start;
+            let glyph = if whitespace.visible {
+                whitespace_glyph(grapheme, trailing)
+            } else {
+                None
+            };
+            let text: Cow<str> = match glyph {
+                // The glyph goes in the first column, so it is only drawn
+                // when that column is visible.
+                Some(glyph) if grapheme_start >= start => {
+                    Cow::Owned(format!("{}{}", glyph, " ".repeat(visible - 1)))
+                }
+                Some(_) => Cow::Owned(" ".repeat(visible)),
+                None if grapheme == "\t" || visible < width => Cow::Owned(" ".repeat(visible)),
+                None => Cow::Borrowed(grapheme),
+            };
+            let highlighting_type = if trailing && whitespace.trailing {
+                highlighting::Type::TrailingWhitespace
+            } else if glyph.is_some() {
+                highlighting::Type::Whitespace
+            } else {
+                *self
                     .highlighting
                     .get(index)
-                    .unwrap_or(&highlighting::Type::None);
-                if highlighting_type != current_highlighting {
-                    current_highlighting = highlighting_type;
-                    let start_highlight =
-                        format!("{}", termion::color::Fg(highlighting_type.to_color()));
-                    result.push_str(&start_highlight[..]);
-                }
-                if c == '\t' {
-                    result.push_str(" ");
-                } else {
-                    result.push(c);
+                    .unwrap_or(&highlighting::Type::None)
+            };
+            match result.last_mut() {
+                Some((run, current_highlighting)) if *current_highlighting == highlighting_type => {
+                    run.push_str(&text);
                 }
+                _ => result.push((text.into_owned(), highlighting_type)),
             }
         }
-        let end_highlight = format!("{}", termion::color::Fg(color::Reset));
-        result.push_str(&end_highlight[..]);
         result
     }
+    /// Returns the display columns at which each screen line starts when the
+    /// row is soft-wrapped at `width` columns.
+    #[allow(clippy::integer_arithmetic)]
+    pub fn wrap(&self, width: usize) -> Vec<usize> {
+        let mut starts = vec![0];
+        let mut line_start = 0;
+        let mut column = 0;
+        for grapheme_width in &self.word_width_index {
+            if column + grapheme_width > line_start + width && column > line_start {
+                starts.push(column);
+                line_start = column;
+            }
+            column += grapheme_width;
+        }
+        starts
+    }
     pub fn len(&self) -> usize {
         self.len
     }
@@ -82,82 +180,129 @@ impl Row {
         self.len == 0
     }
     pub fn insert(&mut self, at: usize, c: char) {
-        if at >= self.word_width_index.len() {
+        if at >= self.len {
             self.string.push(c);
-            self.word_width_index.push(UnicodeWidthChar::width(c).unwrap_or(0));
-            self.len = self.string.len();
-            self.width = self.string.width();
-            return;
-        }
-        let mut result: String = String::new();
-        let mut length = 0;
-        for (index, grapheme) in self.string[..].graphemes(true).enumerate() {
-            length += 1;
-            if index == at {
-                log::info!("{}",index);
-                length += 1;
-                result.push(c);
-                self.word_width_index.insert(index, UnicodeWidthChar::width(c).unwrap_or(0));
+        } else {
+            let mut result: String = String::new();
+            for (index, grapheme) in self.string[..].graphemes(true).enumerate() {
+                if index == at {
+                    result.push(c);
+                }
+                result.push_str(grapheme);
             }
-            result.push_str(grapheme);
+            self.string = result;
         }
-        self.len = length;
-        self.string = result;
+        self.update_widths();
     }
     pub fn delete(&mut self, at: usize) {
-        // if at >= self.len() {
-        if at >= self.word_width_index.len() {
+        if at >= self.len {
             return;
         }
-        let mut word_index: Vec<usize> = vec![];
         let mut result: String = String::new();
-        let mut length = 0;
         for (index, grapheme) in self.string[..].graphemes(true).enumerate() {
             if index != at {
-                length += 1;
                 result.push_str(grapheme);
-                word_index.push(UnicodeWidthStr::width(grapheme));
             }
         }
-        self.len = length;
         self.string = result;
-        self.word_width_index = word_index;
+        self.update_widths();
     }
     pub fn append(&mut self, new: &Self) {
-        for (_, grapheme) in new.string[..].graphemes(true).enumerate() {
-            self.word_width_index.push(UnicodeWidthStr::width(grapheme));
-        }
         self.string = format!("{}{}", self.string, new.string);
-        self.len += new.len;
+        self.update_widths();
     }
     pub fn split(&mut self, at: usize) -> Self {
-        let mut word_index: Vec<usize> = vec![];
         let mut row: String = String::new();
-        let mut length = 0;
         let mut splitted_row: String = String::new();
-        let mut splitted_length = 0;
         for (index, grapheme) in self.string[..].graphemes(true).enumerate() {
             if index < at {
-                length += 1;
                 row.push_str(grapheme);
             } else {
-                splitted_length += 1;
                 splitted_row.push_str(grapheme);
-                word_index.push(unicode_width::UnicodeWidthStr::width(grapheme));
             }
         }
 
         self.string = row;
-        self.len = length;
+        self.update_widths();
         self.is_highlighted = false;
-        Self {
-            width: splitted_row.width().clone(),
-            string: splitted_row,
-            len: splitted_length,
-            is_highlighted: false,
-            highlighting: Vec::new(),
-            word_width_index: word_index,
+        Self::new(&splitted_row[..], self.tab_width)
+    }
+    /// Returns the graphemes from `start` up to, not including, `end`.
+    pub fn slice(&self, start: usize, end: usize) -> String {
+        self.string[..]
+            .graphemes(true)
+            .skip(start)
+            .take(end.saturating_sub(start))
+            .collect()
+    }
+    /// Returns the grapheme index of the start of the word before `at`.
+    pub fn previous_word_start(&self, at: usize) -> usize {
+        let mut classes: Vec<WordClass> = self.string[..]
+            .graphemes(true)
+            .take(at)
+            .map(WordClass::of)
+            .collect();
+        while classes.last() == Some(&WordClass::Space) {
+            classes.pop();
+        }
+        if let Some(class) = classes.last().copied() {
+            while classes.last() == Some(&class) {
+                classes.pop();
+            }
         }
+        classes.len()
+    }
+    /// Returns the grapheme index of the start of the word after `at`, or the
+    /// end of the row.
+    pub fn next_word_start(&self, at: usize) -> usize {
+        let mut classes = self.string[..].graphemes(true).skip(at).map(WordClass::of).peekable();
+        let mut index = at;
+        if let Some(class) = classes.peek().copied() {
+            while classes.peek() == Some(&class) && class != WordClass::Space {
+                classes.next();
+                index = index.saturating_add(1);
+            }
+        }
+        while classes.next() == Some(WordClass::Space) {
+            index = index.saturating_add(1);
+        }
+        cmp::min(index, self.len)
+    }
+    /// Returns the grapheme index of the last grapheme of the word at or
+    /// after `at`, if the row has one.
+    pub fn word_end_from(&self, at: usize) -> Option<usize> {
+        let classes: Vec<WordClass> = self.string[..].graphemes(true).map(WordClass::of).collect();
+        let mut index = at;
+        while classes.get(index) == Some(&WordClass::Space) {
+            index = index.saturating_add(1);
+        }
+        let class = *classes.get(index)?;
+        while classes.get(index.saturating_add(1)) == Some(&class) {
+            index = index.saturating_add(1);
+        }
+        Some(index)
+    }
+    /// Returns the grapheme index of the first grapheme that is not
+    /// whitespace, or the end of the row.
+    pub fn first_non_blank(&self) -> usize {
+        self.string[..]
+            .graphemes(true)
+            .position(|grapheme| WordClass::of(grapheme) != WordClass::Space)
+            .unwrap_or(self.len)
+    }
+    /// Returns the grapheme index of the `count`-th `c` after `at`, or
+    /// before it when searching backward.
+    pub fn find_grapheme(&self, c: char, at: usize, count: usize, direction: SearchDirection) -> Option<usize> {
+        let mut buffer = [0; 4];
+        let wanted: &str = c.encode_utf8(&mut buffer);
+        let graphemes: Vec<&str> = self.string[..].graphemes(true).collect();
+        let matches = graphemes.iter().enumerate().filter(|(_, grapheme)| **grapheme == wanted);
+        let index = if direction == SearchDirection::Forward {
+            matches.filter(|(index, _)| *index > at).nth(count.saturating_sub(1))
+        } else {
+            matches.filter(|(index, _)| *index < at).rev().nth(count.saturating_sub(1))
+        };
+        index.map(|(index, _)| index)
     }
     pub fn as_bytes(&self) -> &[u8] {
         self.string.as_bytes()
@@ -221,86 +366,129 @@ impl Row {
         }
     }
 
-    fn highlight_str(
+    /// Highlights the word at `index` if it is a keyword. The word is
+    /// looked up whole, together with a leading punctuation mark for
+    /// keywords such as `#include`.
+    fn highlight_keywords(
         &mut self,
         index: &mut usize,
-        substring: &str,
+        opts: &HighlightingOptions,
         chars: &[char],
-        hl_type: highlighting::Type,
+        offsets: &[usize],
     ) -> bool {
-        if substring.is_empty() {
+        if *index > 0 && chars.get(index.saturating_sub(1)).map_or(false, |c| is_word_char(*c)) {
             return false;
         }
-        for (substring_index, c) in substring.chars().enumerate() {
-            if let Some(next_char) = chars.get(index.saturating_add(substring_index)) {
-                if *next_char != c {
-                    return false;
-                }
-            } else {
-                return false;
-            }
-        }
-        for _ in 0..substring.len() {
+        let end = match chars.get(*index) {
+            Some(c) if is_word_char(*c) => word_end(chars, *index),
+            Some(_) => word_end(chars, index.saturating_add(1)),
+            None => return false,
+        };
+        let word = match (offsets.get(*index), offsets.get(end)) {
+            (Some(&start), Some(&end)) => self.string.get(start..end).unwrap_or_default(),
+            _ => return false,
+        };
+        let hl_type = if is_keyword(opts.primary_keywords(), word) {
+            highlighting::Type::PrimaryKeywords
+        } else if is_keyword(opts.secondary_keywords(), word) {
+            highlighting::Type::SecondaryKeywords
+        } else {
+            return false;
+        };
+        for _ in *index..end {
             self.highlighting.push(hl_type);
             *index += 1;
         }
         true
     }
-    fn highlight_keywords(
+    /// Highlights a Rust attribute such as `#[derive(Debug)]` through its
+    /// closing bracket, or through the end of the row.
+    fn highlight_attribute(
         &mut self,
         index: &mut usize,
+        opts: &HighlightingOptions,
         chars: &[char],
-        keywords: &[String],
-        hl_type: highlighting::Type,
     ) -> bool {
-        if *index > 0 {
-            #[allow(clippy::indexing_slicing, clippy::integer_arithmetic)]
-                let prev_char = chars[*index - 1];
-            if !is_separator(prev_char) {
-                return false;
-            }
+        if !opts.attributes()
+            || !(starts_with_at(chars, *index, "#[") || starts_with_at(chars, *index, "#!["))
+        {
+            return false;
         }
-        for word in keywords {
-            if *index < chars.len().saturating_sub(word.len()) {
-                #[allow(clippy::indexing_slicing, clippy::integer_arithmetic)]
-                    let next_char = chars[*index + word.len()];
-                if !is_separator(next_char) {
-                    continue;
+        let mut depth: usize = 0;
+        let mut end = chars.len();
+        for (position, c) in chars.iter().enumerate().skip(*index) {
+            match c {
+                '[' => depth = depth.saturating_add(1),
+                ']' => {
+                    depth = depth.saturating_sub(1);
+                    if depth == 0 {
+                        end = position.saturating_add(1);
+                        break;
+                    }
                 }
-            }
-
-            if self.highlight_str(index, &word, chars, hl_type) {
-                return true;
+                _ => (),
             }
         }
-        false
+        for _ in *index..end {
+            self.highlighting.push(highlighting::Type::Attribute);
+            *index += 1;
+        }
+        true
     }
-
-    fn highlight_primary_keywords(
+    fn highlight_lifetime(
         &mut self,
         index: &mut usize,
         opts: &HighlightingOptions,
         chars: &[char],
     ) -> bool {
-        self.highlight_keywords(
-            index,
-            chars,
-            opts.primary_keywords(),
-            highlighting::Type::PrimaryKeywords,
-        )
+        let name_start = index.saturating_add(1);
+        if !opts.lifetimes()
+            || chars.get(*index) != Some(&'\'')
+            || !chars.get(name_start).map_or(false, |c| c.is_alphabetic() || *c == '_')
+        {
+            return false;
+        }
+        for _ in *index..word_end(chars, name_start) {
+            self.highlighting.push(highlighting::Type::Lifetime);
+            *index += 1;
+        }
+        true
     }
-    fn highlight_secondary_keywords(
+    /// Highlights a name that is a macro call (`name!`), a CamelCase type,
+    /// or a function being called or defined.
+    fn highlight_name(
         &mut self,
         index: &mut usize,
         opts: &HighlightingOptions,
         chars: &[char],
     ) -> bool {
-        self.highlight_keywords(
-            index,
-            chars,
-            opts.secondary_keywords(),
-            highlighting::Type::SecondaryKeywords,
-        )
+        let starts_word = chars.get(*index).map_or(false, |c| c.is_alphabetic() || *c == '_')
+            && (*index == 0 || !chars.get(index.saturating_sub(1)).map_or(false, |c| is_word_char(*c)));
+        if !starts_word {
+            return false;
+        }
+        let end = word_end(chars, *index);
+        #[allow(clippy::indexing_slicing)]
+        let name = &chars[*index..end];
+        let (hl_type, end) = if opts.macros()
+            && chars.get(end) == Some(&'!')
+            && chars.get(end.saturating_add(1)) != Some(&'=')
+        {
+            (highlighting::Type::Macro, end.saturating_add(1))
+        } else if opts.type_names() && is_type_name(name) {
+            (highlighting::Type::TypeName, end)
+        } else if opts.functions()
+            && (chars.get(end) == Some(&'(') || follows_keyword(chars, *index, opts.function_keywords()))
+        {
+            (highlighting::Type::Function, end)
+        } else {
+            return false;
+        };
+        for _ in *index..end {
+            self.highlighting.push(hl_type);
+            *index += 1;
+        }
+        true
     }
 
     fn highlight_char(
@@ -310,15 +498,17 @@ impl Row {
         c: char,
         chars: &[char],
     ) -> bool {
-        if opts.characters() && c == '\'' {
+        if opts.character() == Some(c) {
             if let Some(next_char) = chars.get(index.saturating_add(1)) {
-                let closing_index = if *next_char == '\\' {
-                    index.saturating_add(3)
+                // An escape such as `'\''` or `'\u{1F600}'` runs to the next
+                // quote.
+                let closing_index = if opts.escape() == Some(*next_char) {
+                    (index.saturating_add(3)..chars.len()).find(|&i| chars.get(i) == Some(&c))
                 } else {
-                    index.saturating_add(2)
+                    Some(index.saturating_add(2))
                 };
-                if let Some(closing_char) = chars.get(closing_index) {
-                    if *closing_char == '\'' {
+                if let Some(closing_index) = closing_index {
+                    if chars.get(closing_index) == Some(&c) {
                         for _ in 0..=closing_index.saturating_sub(*index) {
                             self.highlighting.push(highlighting::Type::Character);
                             *index += 1;
@@ -335,74 +525,154 @@ impl Row {
         &mut self,
         index: &mut usize,
         opts: &HighlightingOptions,
-        c: char,
         chars: &[char],
     ) -> bool {
-        if opts.comments() && c == '/' && *index < chars.len() {
-            if let Some(next_char) = chars.get(index.saturating_add(1)) {
-                if *next_char == '/' {
-                    for _ in *index..chars.len() {
-                        self.highlighting.push(highlighting::Type::Comment);
-                        *index += 1;
-                    }
-                    return true;
-                }
-            };
+        let hl_type = if opts
+            .doc_comments()
+            .iter()
+            .any(|token| is_doc_comment(chars, *index, token, opts))
+        {
+            highlighting::Type::DocComment
+        } else if opts
+            .line_comment()
+            .map_or(false, |token| starts_with_at(chars, *index, token))
+        {
+            highlighting::Type::Comment
+        } else {
+            return false;
+        };
+        for _ in *index..chars.len() {
+            self.highlighting.push(hl_type);
+            *index += 1;
         }
-        false
+        true
     }
-    #[allow(clippy::indexing_slicing, clippy::integer_arithmetic)]
     fn highlight_multiline_comment(
         &mut self,
         index: &mut usize,
         opts: &HighlightingOptions,
-        c: char,
         chars: &[char],
     ) -> bool {
-        if opts.comments() && c == '/' && *index < chars.len() {
-            if let Some(next_char) = chars.get(index.saturating_add(1)) {
-                if *next_char == '*' {
-                    let closing_index =
-                        if let Some(closing_index) = self.string[*index + 2..].find("*/") {
-                            *index + closing_index + 4
-                        } else {
-                            chars.len()
-                        };
-                    for _ in *index..closing_index {
-                        self.highlighting.push(highlighting::Type::MultilineComment);
-                        *index += 1;
-                    }
-                    return true;
-                }
-            };
+        if let Some((start, _)) = opts.block_comment() {
+            if starts_with_at(chars, *index, start) {
+                let from = index.saturating_add(start.chars().count());
+                self.highlight_comment_until(index, opts, from, chars, 1);
+                return true;
+            }
         }
         false
     }
+    /// Highlights a block comment from `index`, inside `depth` comments as
+    /// of `from`, through the `end` that closes the outermost one, or
+    /// through the end of the row if it is not closed.
+    fn highlight_comment_until(
+        &mut self,
+        index: &mut usize,
+        opts: &HighlightingOptions,
+        from: usize,
+        chars: &[char],
+        mut depth: usize,
+    ) {
+        let (start, end) = match opts.block_comment() {
+            Some(delimiters) => delimiters,
+            None => return,
+        };
+        let mut closing_index = None;
+        let mut position = from;
+        while position < chars.len() {
+            if opts.nested_comments() && starts_with_at(chars, position, start) {
+                depth = depth.saturating_add(1);
+                position = position.saturating_add(start.chars().count());
+            } else if starts_with_at(chars, position, end) {
+                depth = depth.saturating_sub(1);
+                position = position.saturating_add(end.chars().count());
+                if depth == 0 {
+                    closing_index = Some(position);
+                    break;
+                }
+            } else {
+                position = position.saturating_add(1);
+            }
+        }
+        if closing_index.is_none() {
+            self.end_state = highlighting::State::BlockComment(depth);
+        }
+        for _ in *index..closing_index.unwrap_or(chars.len()) {
+            self.highlighting.push(highlighting::Type::MultilineComment);
+            *index += 1;
+        }
+    }
 
     fn highlight_string(
         &mut self,
         index: &mut usize,
         opts: &HighlightingOptions,
-        c: char,
         chars: &[char],
     ) -> bool {
-        if opts.strings() && c == '"' {
-            loop {
-                self.highlighting.push(highlighting::Type::String);
-                *index += 1;
-                if let Some(next_char) = chars.get(*index) {
-                    if *next_char == '"' {
-                        break;
-                    }
-                } else {
-                    break;
+        // Prefixes only count at the start of a word.
+        let at_word_start = *index == 0
+            || chars.get(index.saturating_sub(1)).map_or(false, |c| is_separator(*c));
+        for prefix in opts.raw_string_prefixes() {
+            if !at_word_start || !starts_with_at(chars, *index, prefix) {
+                continue;
+            }
+            let hashes_start = index.saturating_add(prefix.chars().count());
+            let hashes = chars.iter().skip(hashes_start).take_while(|c| **c == '#').count();
+            let quote_index = hashes_start.saturating_add(hashes);
+            if chars.get(quote_index) == Some(&'"') {
+                let from = quote_index.saturating_add(1);
+                let open = highlighting::State::RawString(hashes);
+                self.highlight_string_until(index, &raw_string_end(hashes), from, chars, None, open);
+                return true;
+            }
+        }
+        let quote_index = opts
+            .string_prefixes()
+            .iter()
+            .find(|prefix| at_word_start && starts_with_at(chars, *index, prefix))
+            .map_or(*index, |prefix| index.saturating_add(prefix.chars().count()));
+        for (kind, quote) in opts.multiline_strings().iter().enumerate() {
+            if starts_with_at(chars, quote_index, quote) {
+                let from = quote_index.saturating_add(quote.chars().count());
+                let open = highlighting::State::String(kind);
+                self.highlight_string_until(index, quote, from, chars, opts.escape(), open);
+                return true;
+            }
+        }
+        for quote in opts.strings() {
+            if starts_with_at(chars, quote_index, quote) {
+                let from = quote_index.saturating_add(quote.chars().count());
+                let closing_index =
+                    find_closing(chars, from, quote, opts.escape()).unwrap_or(chars.len());
+                for _ in *index..closing_index {
+                    self.highlighting.push(highlighting::Type::String);
+                    *index += 1;
                 }
+                return true;
             }
+        }
+        false
+    }
+    /// Highlights a string from `index` through the first closing `quote`
+    /// at or after `from` that is not escaped. If there is none, the string
+    /// continues on the next row in the `open` state.
+    fn highlight_string_until(
+        &mut self,
+        index: &mut usize,
+        quote: &str,
+        from: usize,
+        chars: &[char],
+        escape: Option<char>,
+        open: highlighting::State,
+    ) {
+        let closing_index = find_closing(chars, from, quote, escape);
+        if closing_index.is_none() {
+            self.end_state = open;
+        }
+        for _ in *index..closing_index.unwrap_or(chars.len()) {
             self.highlighting.push(highlighting::Type::String);
             *index += 1;
-            return true;
         }
-        false
     }
     fn highlight_number(
         &mut self,
@@ -419,65 +689,66 @@ impl Row {
                     return false;
                 }
             }
-            loop {
+            for _ in 0..number_len(chars, *index, opts) {
                 self.highlighting.push(highlighting::Type::Number);
                 *index += 1;
-                if let Some(next_char) = chars.get(*index) {
-                    if *next_char != '.' && !next_char.is_ascii_digit() {
-                        break;
-                    }
-                } else {
-                    break;
-                }
             }
             return true;
         }
         false
     }
-    #[allow(clippy::indexing_slicing, clippy::integer_arithmetic)]
+    /// What the row ended inside of when it was last highlighted.
+    pub fn end_state(&self) -> highlighting::State {
+        self.end_state
+    }
+    /// Highlights the row, starting inside whatever the previous row ended
+    /// in. Returns what this row ends inside of. A row that has not changed
+    /// and starts in the same state as last time is not highlighted again.
     pub fn highlight(
         &mut self,
         opts: &HighlightingOptions,
         word: &Option<String>,
-        start_with_comment: bool,
-    ) -> bool {
-        let chars: Vec<char> = self.string.chars().collect();
-        if self.is_highlighted && word.is_none() {
-            if let Some(hl_type) = self.highlighting.last() {
-                if *hl_type == highlighting::Type::MultilineComment
-                    && self.string.len() > 1
-                    && self.string[self.string.len() - 2..] == *"*/"
-                {
-                    return true;
-                }
-            }
-            return false;
+        start: highlighting::State,
+    ) -> highlighting::State {
+        if self.is_highlighted && word.is_none() && start == self.start_state {
+            return self.end_state;
         }
+        let chars: Vec<char> = self.string.chars().collect();
+        // Where each char starts in `string`, and where the last one ends,
+        // for looking words up without copying them.
+        let offsets: Vec<usize> = self
+            .string
+            .char_indices()
+            .map(|(offset, _)| offset)
+            .chain(iter::once(self.string.len()))
+            .collect();
         self.highlighting = Vec::new();
+        self.start_state = start;
+        self.end_state = highlighting::State::Normal;
         let mut index = 0;
-        let mut in_ml_comment = start_with_comment;
-        if in_ml_comment {
-            let closing_index = if let Some(closing_index) = self.string.find("*/") {
-                closing_index + 2
-            } else {
-                chars.len()
-            };
-            for _ in 0..closing_index {
-                self.highlighting.push(highlighting::Type::MultilineComment);
+        match start {
+            highlighting::State::Normal => (),
+            highlighting::State::BlockComment(depth) => {
+                self.highlight_comment_until(&mut index, opts, 0, &chars, depth);
+            }
+            highlighting::State::String(kind) => {
+                if let Some(quote) = opts.multiline_strings().get(kind) {
+                    self.highlight_string_until(&mut index, quote, 0, &chars, opts.escape(), start);
+                }
+            }
+            highlighting::State::RawString(hashes) => {
+                self.highlight_string_until(&mut index, &raw_string_end(hashes), 0, &chars, None, start);
             }
-            index = closing_index;
         }
         while let Some(c) = chars.get(index) {
-            if self.highlight_multiline_comment(&mut index, &opts, *c, &chars) {
-                in_ml_comment = true;
-                continue;
-            }
-            in_ml_comment = false;
-            if self.highlight_char(&mut index, opts, *c, &chars)
-                || self.highlight_comment(&mut index, opts, *c, &chars)
-                || self.highlight_primary_keywords(&mut index, &opts, &chars)
-                || self.highlight_secondary_keywords(&mut index, &opts, &chars)
-                || self.highlight_string(&mut index, opts, *c, &chars)
+            if self.highlight_multiline_comment(&mut index, opts, &chars)
+                || self.highlight_char(&mut index, opts, *c, &chars)
+                || self.highlight_lifetime(&mut index, opts, &chars)
+                || self.highlight_comment(&mut index, opts, &chars)
+                || self.highlight_attribute(&mut index, opts, &chars)
+                || self.highlight_keywords(&mut index, opts, &chars, &offsets)
+                || self.highlight_name(&mut index, opts, &chars)
+                || self.highlight_string(&mut index, opts, &chars)
                 || self.highlight_number(&mut index, opts, *c, &chars)
             {
                 continue;
@@ -486,18 +757,200 @@ impl Row {
             index += 1;
         }
         self.highlight_match(word);
-        if in_ml_comment && &self.string[self.string.len().saturating_sub(2)..] != "*/" {
-            return true;
-        }
         self.is_highlighted = true;
-        false
+        self.end_state
+    }
+    /// Takes the highlighting of each character from elsewhere, such as a
+    /// syntax tree, and marks the matches of `word` on top.
+    pub fn set_highlighting(&mut self, highlighting: Vec<highlighting::Type>, word: &Option<String>) {
+        self.highlighting = highlighting;
+        self.end_state = highlighting::State::Normal;
+        self.highlight_match(word);
+        self.is_highlighted = true;
+    }
+}
+
+/// Whether `word` is one of `keywords`.
+#[cfg(not(feature = "linear-keyword-scan"))]
+fn is_keyword(keywords: &HashSet<String>, word: &str) -> bool {
+    keywords.contains(word)
+}
+
+/// Whether `word` is one of `keywords`, trying each in turn as the
+/// highlighter did before keywords were kept in hash sets.
+#[cfg(feature = "linear-keyword-scan")]
+fn is_keyword(keywords: &HashSet<String>, word: &str) -> bool {
+    keywords.iter().any(|keyword| keyword == word)
+}
+
+/// Whether `token` occurs in `chars` at `index`.
+fn starts_with_at(chars: &[char], index: usize, token: &str) -> bool {
+    !token.is_empty()
+        && token
+            .chars()
+            .enumerate()
+            .all(|(offset, c)| chars.get(index.saturating_add(offset)) == Some(&c))
+}
+
+/// Whether the doc comment `token` occurs in `chars` at `index`. A token
+/// ending in the last character of the line comment is an ordinary comment
+/// when followed by another one, as `////` is in Rust.
+fn is_doc_comment(chars: &[char], index: usize, token: &str, opts: &HighlightingOptions) -> bool {
+    if !starts_with_at(chars, index, token) {
+        return false;
     }
+    let repeated = opts.line_comment().and_then(|line_comment| line_comment.chars().last());
+    let next = chars.get(index.saturating_add(token.chars().count())).copied();
+    !(repeated.is_some() && token.chars().last() == repeated && next == repeated)
+}
+
+/// The index just past the first `quote` at or after `from` that does not
+/// follow an `escape`.
+fn find_closing(chars: &[char], from: usize, quote: &str, escape: Option<char>) -> Option<usize> {
+    let mut index = from;
+    while let Some(c) = chars.get(index) {
+        if Some(*c) == escape {
+            index = index.saturating_add(2);
+        } else if starts_with_at(chars, index, quote) {
+            return Some(index.saturating_add(quote.chars().count()));
+        } else {
+            index = index.saturating_add(1);
+        }
+    }
+    None
+}
+
+/// The length of the number literal at `index`, which starts with a
+/// digit.
+fn number_len(chars: &[char], index: usize, opts: &HighlightingOptions) -> usize {
+    let separator = opts.number_separator();
+    // Digits of `radix`, and separators between them.
+    let digits = |from: usize, radix: u32| {
+        chars
+            .iter()
+            .skip(from)
+            .take_while(|c| c.is_digit(radix) || Some(**c) == separator)
+            .count()
+            .saturating_add(from)
+    };
+    let is_digit = |at: usize| chars.get(at).map_or(false, char::is_ascii_digit);
+    let prefix = opts.number_prefixes().iter().find(|(prefix, _)| {
+        prefix.chars().enumerate().all(|(offset, c)| {
+            chars
+                .get(index.saturating_add(offset))
+                .map_or(false, |next| next.eq_ignore_ascii_case(&c))
+        })
+    });
+    let mut end = if let Some((prefix, radix)) = prefix {
+        digits(index.saturating_add(prefix.chars().count()), *radix)
+    } else {
+        let mut end = digits(index, 10);
+        // A fraction needs a digit after the point, so that `1..2` and
+        // `1.max(2)` are left alone.
+        if chars.get(end) == Some(&'.') && is_digit(end.saturating_add(1)) {
+            end = digits(end.saturating_add(1), 10);
+        }
+        if opts.number_exponents() && matches!(chars.get(end), Some('e' | 'E')) {
+            let sign = usize::from(matches!(chars.get(end.saturating_add(1)), Some('+' | '-')));
+            let exponent = end.saturating_add(1).saturating_add(sign);
+            if is_digit(exponent) {
+                end = digits(exponent, 10);
+            }
+        }
+        end
+    };
+    if let Some(suffix) = opts
+        .number_suffixes()
+        .iter()
+        .find(|suffix| starts_with_at(chars, end, suffix))
+    {
+        end = end.saturating_add(suffix.chars().count());
+    }
+    end.saturating_sub(index)
+}
+
+/// What ends a raw string opened with `hashes` `#`s.
+fn raw_string_end(hashes: usize) -> String {
+    format!("\"{}", "#".repeat(hashes))
+}
+
+fn is_word_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+/// The index just past the name that starts at `index`.
+fn word_end(chars: &[char], index: usize) -> usize {
+    chars
+        .iter()
+        .skip(index)
+        .take_while(|c| is_word_char(**c))
+        .count()
+        .saturating_add(index)
+}
+
+/// Whether `name` is CamelCase, or a single capital such as `T`.
+fn is_type_name(name: &[char]) -> bool {
+    name.first().map_or(false, char::is_ascii_uppercase)
+        && (name.len() == 1 || name.iter().any(char::is_ascii_lowercase))
+}
+
+/// Whether the name at `index` comes right after one of `keywords`, as
+/// `main` does in `fn main`.
+fn follows_keyword(chars: &[char], index: usize, keywords: &[String]) -> bool {
+    let blank = chars
+        .iter()
+        .take(index)
+        .rev()
+        .take_while(|c| c.is_whitespace())
+        .count();
+    let keyword_end = index.saturating_sub(blank);
+    blank > 0
+        && keywords.iter().any(|keyword| {
+            let keyword_start = keyword_end.checked_sub(keyword.chars().count());
+            keyword_start.map_or(false, |start| {
+                starts_with_at(chars, start, keyword)
+                    && (start == 0
+                        || !chars.get(start.saturating_sub(1)).map_or(false, |c| is_word_char(*c)))
+            })
+        })
 }
 
 fn is_separator(c: char) -> bool {
     c.is_ascii_punctuation() || c.is_ascii_whitespace()
 }
 
+/// The glyph drawn in the first column of a whitespace grapheme when
+/// whitespace is visible. Plain spaces only get one when `trailing`.
+fn whitespace_glyph(grapheme: &str, trailing: bool) -> Option<&'static str> {
+    match grapheme {
+        "\t" => Some("»"),
+        "\u{a0}" => Some("⍽"),
+        "\u{3000}" => Some("▢"),
+        " " if trailing => Some("⋅"),
+        _ => None,
+    }
+}
+
+/// What a grapheme counts as when moving by words.
+#[derive(PartialEq, Clone, Copy)]
+enum WordClass {
+    Space,
+    Word,
+    Punctuation,
+}
+
+impl WordClass {
+    fn of(grapheme: &str) -> Self {
+        if grapheme.chars().all(char::is_whitespace) {
+            Self::Space
+        } else if grapheme.chars().any(|c| c.is_alphanumeric() || c == '_') {
+            Self::Word
+        } else {
+            Self::Punctuation
+        }
+    }
+}
+
 #[cfg(test)]
 mod test_super {
     use super::*;
@@ -540,4 +993,229 @@ mod test_super {
         assert_eq!(row.find("t", 2, SearchDirection::Forward), Some(4));
         assert_eq!(row.find("t", 5, SearchDirection::Forward), Some(5));
     }
+
+    #[test]
+    fn test_render_whitespace() {
+        use highlighting::Type;
+        let row = Row::new("\ta\u{a0}b\u{3000}c  ", 4);
+        let tab = " ".repeat(3);
+        let visible = Whitespace {
+            visible: true,
+            trailing: false,
+        };
+        let text = |runs: Vec<(String, Type)>| runs.into_iter().map(|(text, _)| text).collect::<String>();
+        assert_eq!(text(row.render(0, 80, Whitespace::default())), format!(" {}a\u{a0}b\u{3000}c  ", tab));
+        let runs = row.render(0, 80, visible);
+        assert_eq!(runs[0], (format!("»{}", tab), Type::Whitespace));
+        assert_eq!(text(runs.clone()), format!("»{}a⍽b▢ c⋅⋅", tab));
+        assert_eq!(runs.last(), Some(&(String::from("⋅⋅"), Type::Whitespace)));
+        // A tab whose first column is scrolled off has no glyph.
+        assert_eq!(row.render(1, 80, visible)[0], (tab.clone(), Type::Whitespace));
+
+        let trailing = Whitespace {
+            visible: false,
+            trailing: true,
+        };
+        let runs = row.render(0, 80, trailing);
+        assert_eq!(runs.last(), Some(&(String::from("  "), Type::TrailingWhitespace)));
+        assert_eq!(runs[0].1, Type::None);
+    }
+
+    #[test]
+    fn test_word_starts() {
+        let row = Row::from("let  x_1 = 中文;");
+        assert_eq!(row.next_word_start(0), 5);
+        assert_eq!(row.next_word_start(5), 9);
+        assert_eq!(row.next_word_start(9), 11);
+        assert_eq!(row.next_word_start(11), 13);
+        assert_eq!(row.next_word_start(13), 14);
+        assert_eq!(row.previous_word_start(14), 13);
+        assert_eq!(row.previous_word_start(11), 9);
+        assert_eq!(row.previous_word_start(5), 0);
+        assert_eq!(row.previous_word_start(0), 0);
+    }
+
+    #[test]
+    fn test_vim_motions() {
+        let row = Row::from("  let x_1 = a.b;");
+        assert_eq!(row.first_non_blank(), 2);
+        assert_eq!(row.word_end_from(1), Some(4));
+        assert_eq!(row.word_end_from(5), Some(8));
+        assert_eq!(row.word_end_from(14), Some(14));
+        assert_eq!(row.word_end_from(16), None);
+        assert_eq!(row.find_grapheme('b', 2, 1, SearchDirection::Forward), Some(14));
+        assert_eq!(row.find_grapheme(' ', 2, 2, SearchDirection::Forward), Some(9));
+        assert_eq!(row.find_grapheme(' ', 9, 1, SearchDirection::Backward), Some(5));
+        assert_eq!(row.find_grapheme('z', 0, 1, SearchDirection::Forward), None);
+    }
+
+    #[test]
+    fn test_highlight_comments() {
+        use highlighting::{State, Type};
+        let file_type = crate::FileType::detect("main.rs", None);
+        let opts = file_type.highlighting_options();
+        let mut row = Row::from("中 /* a */ \"s\" // b");
+        assert_eq!(row.highlight(opts, &None, State::Normal), State::Normal);
+        let mut expected = vec![Type::None; 2];
+        expected.extend(vec![Type::MultilineComment; 7]);
+        expected.push(Type::None);
+        expected.extend(vec![Type::String; 3]);
+        expected.push(Type::None);
+        expected.extend(vec![Type::Comment; 4]);
+        assert_eq!(row.highlighting, expected);
+
+        let mut row = Row::from("a */ b /* c");
+        assert_eq!(row.highlight(opts, &None, State::BlockComment(1)), State::BlockComment(1));
+        assert_eq!(row.highlighting[..4], [Type::MultilineComment; 4]);
+        assert_eq!(row.highlighting[4], Type::None);
+        // A highlighted row remembers that it ends inside a comment, until
+        // it starts in another state.
+        assert_eq!(row.highlight(opts, &None, State::BlockComment(1)), State::BlockComment(1));
+        assert_eq!(row.highlight(opts, &None, State::Normal), State::BlockComment(1));
+        assert_eq!(row.highlighting[0], Type::None);
+
+        // Rust block comments nest.
+        let mut row = Row::from("/* a /* b */ c");
+        assert_eq!(row.highlight(opts, &None, State::Normal), State::BlockComment(1));
+        let mut row = Row::from("/* */ */ d");
+        assert_eq!(row.highlight(opts, &None, State::BlockComment(1)), State::Normal);
+        assert_eq!(row.highlighting[..8], [Type::MultilineComment; 8]);
+        assert_eq!(row.highlighting[8..], [Type::None; 2]);
+
+        let mut row = Row::from("//! a");
+        row.highlight(opts, &None, State::Normal);
+        assert_eq!(row.highlighting, [Type::DocComment; 5]);
+        let mut row = Row::from("/// a");
+        row.highlight(opts, &None, State::Normal);
+        assert_eq!(row.highlighting, [Type::DocComment; 5]);
+        // More than three slashes make an ordinary comment.
+        for text in ["//// a", "/////"] {
+            let mut row = Row::from(text);
+            row.highlight(opts, &None, State::Normal);
+            assert_eq!(row.highlighting, vec![Type::Comment; text.len()]);
+        }
+    }
+
+    #[test]
+    fn test_highlight_numbers() {
+        use highlighting::{State, Type};
+        let file_type = crate::FileType::detect("main.rs", None);
+        let opts = file_type.highlighting_options();
+        let numbers = |text: &str| -> Vec<String> {
+            let mut row = Row::from(text);
+            row.highlight(opts, &None, State::Normal);
+            let mut numbers: Vec<String> = Vec::new();
+            let mut in_number = false;
+            for (c, hl_type) in text.chars().zip(&row.highlighting) {
+                match numbers.last_mut() {
+                    Some(number) if in_number && *hl_type == Type::Number => number.push(c),
+                    _ if *hl_type == Type::Number => numbers.push(c.to_string()),
+                    _ => (),
+                }
+                in_number = *hl_type == Type::Number;
+            }
+            numbers
+        };
+        assert_eq!(
+            numbers("0xFF 0b1010 0o17 1_000_000 1e-9 42u64 3.0f32 0XAu8"),
+            ["0xFF", "0b1010", "0o17", "1_000_000", "1e-9", "42u64", "3.0f32", "0XAu8"]
+        );
+        assert_eq!(numbers("0..10 1.max(2) x.0 2E+3"), ["0", "10", "1", "2", "0", "2E+3"]);
+        assert_eq!(numbers("1e 1.5e+ v1"), ["1", "1.5"]);
+    }
+
+    #[test]
+    fn test_highlight_keywords() {
+        use highlighting::{State, Type};
+        let file_type = crate::FileType::detect("main.c", None);
+        let mut row = Row::from("#include x_int int8_t intx int");
+        row.highlight(file_type.highlighting_options(), &None, State::Normal);
+        let keywords: Vec<(String, Type)> = row
+            .render(0, 80, Whitespace::default())
+            .into_iter()
+            .filter(|(_, hl_type)| *hl_type != Type::None)
+            .collect();
+        assert_eq!(
+            keywords,
+            [
+                (String::from("#include"), Type::PrimaryKeywords),
+                (String::from("int8_t"), Type::SecondaryKeywords),
+                (String::from("int"), Type::SecondaryKeywords),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_highlight_names() {
+        use highlighting::{State, Type};
+        let file_type = crate::FileType::detect("main.rs", None);
+        let opts = file_type.highlighting_options();
+        let types = |text: &str| -> Vec<(String, Type)> {
+            let mut row = Row::from(text);
+            row.highlight(opts, &None, State::Normal);
+            row.render(0, 80, Whitespace::default())
+                .into_iter()
+                .filter(|(_, hl_type)| *hl_type != Type::None)
+                .collect()
+        };
+        assert_eq!(
+            types("#[derive(Debug)] fn parse<'a>(s: &'a u8) -> Option<T> {"),
+            [
+                (String::from("#[derive(Debug)]"), Type::Attribute),
+                (String::from("fn"), Type::PrimaryKeywords),
+                (String::from("parse"), Type::Function),
+                (String::from("'a"), Type::Lifetime),
+                (String::from("'a"), Type::Lifetime),
+                (String::from("u8"), Type::SecondaryKeywords),
+                (String::from("Option"), Type::TypeName),
+                (String::from("T"), Type::TypeName),
+            ]
+        );
+        assert_eq!(
+            types("println!(\"{}\", x.len()); a != b; MAX_LEN; foo_Bar"),
+            [
+                (String::from("println!"), Type::Macro),
+                (String::from("\"{}\""), Type::String),
+                (String::from("len"), Type::Function),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_highlight_strings() {
+        use highlighting::{State, Type};
+        let file_type = crate::FileType::detect("main.rs", None);
+        let opts = file_type.highlighting_options();
+        let mut row = Row::from(r#"a("\"", b"x", '\'', '\u{1F600}')"#);
+        assert_eq!(row.highlight(opts, &None, State::Normal), State::Normal);
+        let mut expected = vec![Type::Function, Type::None];
+        expected.extend(vec![Type::String; 4]);
+        expected.extend(vec![Type::None; 2]);
+        expected.extend(vec![Type::String; 4]);
+        expected.extend(vec![Type::None; 2]);
+        expected.extend(vec![Type::Character; 4]);
+        expected.extend(vec![Type::None; 2]);
+        expected.extend(vec![Type::Character; 11]);
+        expected.push(Type::None);
+        assert_eq!(row.highlighting, expected);
+
+        // Strings may span rows; a raw string ignores escapes and `"` without
+        // enough `#`s.
+        let mut row = Row::from(r##"x = br#"a\"#;"##);
+        assert_eq!(row.highlight(opts, &None, State::Normal), State::Normal);
+        assert_eq!(row.highlighting[4..12], [Type::String; 8]);
+        assert_eq!(row.highlighting[12], Type::None);
+        let mut row = Row::from(r##"r#"a "b""##);
+        assert_eq!(row.highlight(opts, &None, State::Normal), State::RawString(1));
+        let mut row = Row::from(r##"c"#; "d\"##);
+        assert_eq!(row.highlight(opts, &None, State::RawString(1)), State::String(0));
+        assert_eq!(row.highlighting[..3], [Type::String; 3]);
+        assert_eq!(row.highlighting[3..5], [Type::None; 2]);
+        assert_eq!(row.highlighting[5..], [Type::String; 3]);
+        // A letter in a word is not a prefix.
+        let mut row = Row::from(r#"bar"x""#);
+        row.highlight(opts, &None, State::Normal);
+        assert_eq!(row.highlighting[2], Type::None);
+        assert_eq!(row.highlighting[3], Type::String);
+    }
 }
@@ -1,77 +1,300 @@
+use crate::Backend;
+use crate::ColorSupport;
+use crate::CrosstermBackend;
 use crate::Position;
-use std::io::{self, stdout, Write, ErrorKind};
+use std::cmp;
+use std::io;
+use std::time::Duration;
 
-use crossterm::event::{KeyCode, KeyEvent};
-use crossterm::event::read;
-use crossterm::event;
-use crossterm::terminal::enable_raw_mode;
-use crossterm::terminal::ClearType;
+use crossterm::event::Event;
 use crossterm::style::Color;
-use std::fs::read_to_string;
-
+use unicode_segmentation::UnicodeSegmentation;
+use unicode_width::UnicodeWidthStr;
 
 pub struct Size {
     pub width: u16,
     pub height: u16,
 }
 
+/// Colors and attributes of drawn text. Unset colors use the terminal's
+/// defaults.
+#[derive(Clone, Copy, PartialEq, Debug, Default)]
+pub struct Style {
+    pub fg: Option<Color>,
+    pub bg: Option<Color>,
+    pub bold: bool,
+    pub italic: bool,
+    pub underline: bool,
+}
+
+impl Style {
+    /// Returns this style with the colors and attributes set in `other`
+    /// applied on top.
+    pub fn patch(self, other: Self) -> Self {
+        Self {
+            fg: other.fg.or(self.fg),
+            bg: other.bg.or(self.bg),
+            bold: self.bold || other.bold,
+            italic: self.italic || other.italic,
+            underline: self.underline || other.underline,
+        }
+    }
+}
+
+/// One screen cell. The trailing cells covered by a wide grapheme hold an
+/// empty symbol.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Cell {
+    pub symbol: String,
+    pub style: Style,
+}
+
+impl Default for Cell {
+    fn default() -> Self {
+        Self {
+            symbol: String::from(" "),
+            style: Style::default(),
+        }
+    }
+}
+
+/// A frame worth of cells, drawn into by the editor and diffed against the
+/// previously flushed frame.
+pub struct Buffer {
+    width: u16,
+    height: u16,
+    cells: Vec<Cell>,
+}
+
+impl Buffer {
+    pub fn empty(width: u16, height: u16) -> Self {
+        Self {
+            width,
+            height,
+            cells: vec![Cell::default(); usize::from(width) * usize::from(height)],
+        }
+    }
+    pub fn reset(&mut self) {
+        self.fill(Style::default());
+    }
+    /// Blanks every cell with the given style.
+    pub fn fill(&mut self, style: Style) {
+        for cell in &mut self.cells {
+            *cell = Cell {
+                symbol: String::from(" "),
+                style,
+            };
+        }
+    }
+    fn index_of(&self, x: u16, y: u16) -> usize {
+        usize::from(y) * usize::from(self.width) + usize::from(x)
+    }
+    #[allow(clippy::integer_arithmetic, clippy::integer_division)]
+    fn pos_of(&self, index: usize) -> (u16, u16) {
+        let width = usize::from(self.width);
+        ((index % width) as u16, (index / width) as u16)
+    }
+    /// Writes `string` starting at column `x`, clipped to the buffer width,
+    /// and returns the column following the last written grapheme.
+    #[allow(clippy::cast_possible_truncation, clippy::integer_arithmetic)]
+    pub fn set_string(&mut self, mut x: u16, y: u16, string: &str, style: Style) -> u16 {
+        if y >= self.height {
+            return x;
+        }
+        for grapheme in string.graphemes(true) {
+            let width = grapheme.width() as u16;
+            if width == 0 {
+                continue;
+            }
+            if x.saturating_add(width) > self.width {
+                break;
+            }
+            let index = self.index_of(x, y);
+            self.cells[index] = Cell {
+                symbol: String::from(grapheme),
+                style,
+            };
+            for covered in 1..usize::from(width) {
+                self.cells[index + covered] = Cell {
+                    symbol: String::new(),
+                    style,
+                };
+            }
+            x += width;
+        }
+        x
+    }
+    /// Applies `style` on top of the style of `width` cells starting at
+    /// column `x`.
+    pub fn set_style(&mut self, x: u16, y: u16, width: u16, style: Style) {
+        if y >= self.height {
+            return;
+        }
+        let end = cmp::min(x.saturating_add(width), self.width);
+        for column in x..end {
+            let index = self.index_of(column, y);
+            if let Some(cell) = self.cells.get_mut(index) {
+                cell.style = cell.style.patch(style);
+            }
+        }
+    }
+    /// Returns the cells of `next` that have to be written to turn the screen
+    /// showing `self` into `next`, skipping cells hidden behind wide graphemes.
+    pub fn diff<'a>(&self, next: &'a Self) -> Vec<(u16, u16, &'a Cell)> {
+        let mut updates = Vec::new();
+        let mut invalidated = 0;
+        let mut to_skip = 0;
+        for (index, (current, previous)) in next.cells.iter().zip(self.cells.iter()).enumerate() {
+            if (current != previous || invalidated > 0) && to_skip == 0 {
+                let (x, y) = next.pos_of(index);
+                updates.push((x, y, current));
+            }
+            to_skip = current.symbol.width().saturating_sub(1);
+            let affected = cmp::max(current.symbol.width(), previous.symbol.width());
+            invalidated = cmp::max(affected, invalidated).saturating_sub(1);
+        }
+        updates
+    }
+}
+
+/// Screen state of the editor on top of a `Backend`.
+///
+/// Drawing goes into an off-screen `Buffer`; `flush` only sends the cells
+/// that changed since the previous frame to the backend.
 pub struct Terminal {
+    backend: Box<dyn Backend>,
     size: Size,
+    current: Buffer,
+    previous: Buffer,
+    cursor: (u16, u16),
+    needs_clear: bool,
+    color_support: ColorSupport,
 }
 
 impl Terminal {
     pub fn default() -> Result<Self, std::io::Error> {
-        let size = termion::terminal_size()?;
+        Self::new(Box::new(CrosstermBackend::new()?))
+    }
+    pub fn new(backend: Box<dyn Backend>) -> Result<Self, std::io::Error> {
+        let (width, height) = backend.size()?;
         Ok(Self {
+            backend,
             size: Size {
-                width: size.0,
-                height: size.1.saturating_sub(2),
+                width,
+                height: height.saturating_sub(2),
             },
-
+            current: Buffer::empty(width, height),
+            previous: Buffer::empty(width, height),
+            cursor: (0, 0),
+            needs_clear: true,
+            color_support: ColorSupport::detect(),
         })
     }
     pub fn size(&self) -> &Size {
         &self.size
     }
-    pub fn clear_screen() {
-        print!("{}", crossterm::terminal::Clear(ClearType::All));
+    /// Adapts the buffers to a new terminal size and redraws everything on
+    /// the next flush.
+    pub fn resize(&mut self, width: u16, height: u16) {
+        self.size = Size {
+            width,
+            height: height.saturating_sub(2),
+        };
+        self.current = Buffer::empty(width, height);
+        self.previous = Buffer::empty(width, height);
+        self.needs_clear = true;
     }
-
-    #[allow(clippy::cast_possible_truncation)]
-    pub fn cursor_position(position: &Position) {
-        let Position { mut x, x_word_index, mut y } = position;
-        let x = x as u16;
-        let y = y as u16;
-        print!("{}", crossterm::cursor::MoveTo(x, y));
+    pub fn color_support(&self) -> ColorSupport {
+        self.color_support
     }
-    pub fn flush() -> Result<(), std::io::Error> {
-        io::stdout().flush()
+    pub fn set_color_support(&mut self, color_support: ColorSupport) {
+        self.color_support = color_support;
     }
-
-    pub fn read_key() -> Result<KeyEvent, std::io::Error> {
-        if let event::Event::Key(key) = read()? {
-            return Ok(key);
+    /// Blanks the current frame with `style`, typically the theme's text
+    /// style, before drawing into it.
+    pub fn fill(&mut self, style: Style) {
+        let style = self.quantize(style);
+        self.current.fill(style);
+    }
+    fn quantize(&self, style: Style) -> Style {
+        Style {
+            fg: style.fg.map(|color| self.color_support.quantize(color)),
+            bg: style.bg.map(|color| self.color_support.quantize(color)),
+            ..style
         }
-        Err(std::io::Error::new(ErrorKind::Other, "oh no!"))
     }
-    pub fn cursor_hide() {
-        print!("{}", crossterm::cursor::Hide);
+    /// Draws `string` into the current frame. Colors are reduced to what the
+    /// terminal supports here, so callers can always use RGB colors.
+    pub fn set_string(&mut self, x: u16, y: u16, string: &str, style: Style) -> u16 {
+        let style = self.quantize(style);
+        self.current.set_string(x, y, string, style)
     }
-    pub fn cursor_show() {
-        print!("{}", crossterm::cursor::Show);
+    /// Applies `style` on top of already drawn cells, e.g. to mark a
+    /// selection.
+    pub fn set_style(&mut self, x: u16, y: u16, width: u16, style: Style) {
+        let style = self.quantize(style);
+        self.current.set_style(x, y, width, style);
     }
-    pub fn clear_current_line() {
-        print!("{}", crossterm::terminal::Clear(ClearType::CurrentLine));
+
+    #[allow(clippy::cast_possible_truncation)]
+    pub fn cursor_position(&mut self, position: &Position) {
+        self.cursor = (position.x as u16, position.y as u16);
     }
-    pub fn set_bg_color(color: Color) {
-        print!("{}", crossterm::style::SetBackgroundColor(color));
+    /// Sends the difference between the drawn frame and the previous one to
+    /// the backend and starts a new, blank frame.
+    pub fn flush(&mut self) -> Result<(), std::io::Error> {
+        self.backend.hide_cursor()?;
+        if self.needs_clear {
+            self.backend.clear()?;
+            self.previous.reset();
+            self.needs_clear = false;
+        }
+        self.backend.draw(&self.previous.diff(&self.current))?;
+        self.backend.set_cursor(self.cursor.0, self.cursor.1)?;
+        self.backend.show_cursor()?;
+        self.backend.flush()?;
+        std::mem::swap(&mut self.previous, &mut self.current);
+        self.current.reset();
+        Ok(())
     }
 
-    pub fn set_fg_color(color: Color) {
-        print!("{}", crossterm::style::SetForegroundColor(color));
+    pub fn poll_event(&mut self, timeout: Duration) -> Result<bool, io::Error> {
+        self.backend.poll(timeout)
+    }
+    pub fn read_event(&mut self) -> Result<Event, io::Error> {
+        self.backend.read_event()
     }
+}
+
+#[cfg(test)]
+mod test_super {
+    use super::*;
+
+    #[test]
+    fn test_buffer_diff() {
+        let mut previous = Buffer::empty(6, 2);
+        previous.set_string(0, 0, "ab中", Style::default());
+        let mut next = Buffer::empty(6, 2);
+        next.set_string(0, 0, "ab中d", Style::default());
+        let red = Style {
+            fg: Some(Color::Red),
+            ..Style::default()
+        };
+        next.set_string(0, 1, "x", red);
+        let updates: Vec<(u16, u16, &str)> = previous
+            .diff(&next)
+            .into_iter()
+            .map(|(x, y, cell)| (x, y, cell.symbol.as_str()))
+            .collect();
+        assert_eq!(updates, vec![(4, 0, "d"), (0, 1, "x")]);
 
-    pub fn reset_color() {
-        print!("{}", crossterm::style::ResetColor);
+        let mut replaced = Buffer::empty(6, 2);
+        replaced.set_string(0, 0, "abcd", Style::default());
+        let updates: Vec<(u16, u16, &str)> = next
+            .diff(&replaced)
+            .into_iter()
+            .map(|(x, y, cell)| (x, y, cell.symbol.as_str()))
+            .collect();
+        assert_eq!(updates, vec![(2, 0, "c"), (3, 0, "d"), (4, 0, " "), (0, 1, " ")]);
     }
 }
@@ -0,0 +1,250 @@
+use crate::config::config_dir;
+use crate::highlighting;
+use crate::Style;
+use crossterm::style::Color;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fs;
+use std::io::{Error, ErrorKind};
+use std::path::PathBuf;
+
+const BUILTIN_THEMES: [(&str, &str); 3] = [
+    ("dark", include_str!("../themes/dark.toml")),
+    ("light", include_str!("../themes/light.toml")),
+    ("mono", include_str!("../themes/mono.toml")),
+];
+
+#[derive(Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+struct StyleDef {
+    fg: Option<String>,
+    bg: Option<String>,
+    #[serde(default)]
+    bold: bool,
+    #[serde(default)]
+    italic: bool,
+    #[serde(default)]
+    underline: bool,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ThemeFile {
+    name: Option<String>,
+    #[serde(default)]
+    ui: HashMap<String, StyleDef>,
+    #[serde(default)]
+    syntax: HashMap<String, StyleDef>,
+}
+
+/// Styles for every highlighting type and every part of the editor UI.
+#[derive(Clone)]
+pub struct Theme {
+    name: String,
+    text: Style,
+    status_bar: Style,
+    message_bar: Style,
+    gutter: Style,
+    selection: Style,
+    cursor_line: Style,
+    ruler: Style,
+    syntax: HashMap<highlighting::Type, Style>,
+}
+
+impl Default for Theme {
+    fn default() -> Self {
+        Self::builtin("dark").unwrap_or_else(|| Self {
+            name: String::from("dark"),
+            text: Style::default(),
+            status_bar: Style::default(),
+            message_bar: Style::default(),
+            gutter: Style::default(),
+            selection: Style::default(),
+            cursor_line: Style::default(),
+            ruler: Style::default(),
+            syntax: HashMap::new(),
+        })
+    }
+}
+
+impl Theme {
+    pub fn builtin_names() -> Vec<&'static str> {
+        BUILTIN_THEMES.iter().map(|(name, _)| *name).collect()
+    }
+    pub fn builtin(name: &str) -> Option<Self> {
+        BUILTIN_THEMES
+            .iter()
+            .find(|(builtin_name, _)| *builtin_name == name)
+            .and_then(|(_, source)| Self::from_toml(source, name).ok())
+    }
+    /// Loads a built-in theme by name, a theme file by path, or
+    /// `themes/<name>.toml` from the config directory.
+    pub fn load(name: &str) -> Result<Self, Error> {
+        if let Some(theme) = Self::builtin(name) {
+            return Ok(theme);
+        }
+        let mut path = PathBuf::from(name);
+        if !path.is_file() {
+            if let Some(dir) = config_dir() {
+                path = dir.join("themes").join(format!("{}.toml", name));
+            }
+        }
+        let source = fs::read_to_string(&path)?;
+        let default_name = path
+            .file_stem()
+            .map_or(name, |stem| stem.to_str().unwrap_or(name));
+        Self::from_toml(&source, default_name)
+    }
+    pub fn from_toml(source: &str, default_name: &str) -> Result<Self, Error> {
+        let file: ThemeFile =
+            toml::from_str(source).map_err(|err| Error::new(ErrorKind::InvalidData, err.to_string()))?;
+        let mut theme = Self {
+            name: file.name.unwrap_or_else(|| default_name.to_string()),
+            text: Style::default(),
+            status_bar: Style::default(),
+            message_bar: Style::default(),
+            gutter: Style::default(),
+            selection: Style::default(),
+            cursor_line: Style::default(),
+            ruler: Style::default(),
+            syntax: HashMap::new(),
+        };
+        for (key, definition) in &file.ui {
+            let style = parse_style(definition)?;
+            match key.as_str() {
+                "text" => theme.text = style,
+                "status_bar" => theme.status_bar = style,
+                "message_bar" => theme.message_bar = style,
+                "gutter" => theme.gutter = style,
+                "selection" => theme.selection = style,
+                "cursor_line" => theme.cursor_line = style,
+                "ruler" => theme.ruler = style,
+                _ => return Err(invalid(format!("unknown ui element `{}`", key))),
+            }
+        }
+        for (key, definition) in &file.syntax {
+            let hl_type = highlighting::Type::from_name(key)
+                .ok_or_else(|| invalid(format!("unknown syntax type `{}`", key)))?;
+            theme.syntax.insert(hl_type, parse_style(definition)?);
+        }
+        Ok(theme)
+    }
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+    pub fn text(&self) -> Style {
+        self.text
+    }
+    pub fn status_bar(&self) -> Style {
+        self.text.patch(self.status_bar)
+    }
+    pub fn message_bar(&self) -> Style {
+        self.text.patch(self.message_bar)
+    }
+    pub fn gutter(&self) -> Style {
+        self.text.patch(self.gutter)
+    }
+    pub fn selection(&self) -> Style {
+        self.selection
+    }
+    pub fn cursor_line(&self) -> Style {
+        self.cursor_line
+    }
+    pub fn ruler(&self) -> Style {
+        self.ruler
+    }
+    /// The style for text of the given highlighting type.
+    pub fn syntax(&self, hl_type: highlighting::Type) -> Style {
+        self.syntax
+            .get(&hl_type)
+            .map_or(self.text, |style| self.text.patch(*style))
+    }
+}
+
+fn invalid(message: String) -> Error {
+    Error::new(ErrorKind::InvalidData, message)
+}
+
+fn parse_style(definition: &StyleDef) -> Result<Style, Error> {
+    Ok(Style {
+        fg: definition.fg.as_deref().map(parse_color).transpose()?,
+        bg: definition.bg.as_deref().map(parse_color).transpose()?,
+        bold: definition.bold,
+        italic: definition.italic,
+        underline: definition.underline,
+    })
+}
+
+/// Parses `#rrggbb`, a 256-color palette index or one of the 16 ANSI color
+/// names.
+fn parse_color(value: &str) -> Result<Color, Error> {
+    if let Some(hex) = value.strip_prefix('#') {
+        if hex.len() == 6 && hex.is_ascii() {
+            let channel = |range| u8::from_str_radix(&hex[range], 16);
+            if let (Ok(r), Ok(g), Ok(b)) = (channel(0..2), channel(2..4), channel(4..6)) {
+                return Ok(Color::Rgb { r, g, b });
+            }
+        }
+        return Err(invalid(format!("invalid color `{}`", value)));
+    }
+    if let Ok(index) = value.parse::<u8>() {
+        return Ok(Color::AnsiValue(index));
+    }
+    let color = match value {
+        "default" | "reset" => Color::Reset,
+        "black" => Color::Black,
+        "dark_grey" => Color::DarkGrey,
+        "red" => Color::Red,
+        "dark_red" => Color::DarkRed,
+        "green" => Color::Green,
+        "dark_green" => Color::DarkGreen,
+        "yellow" => Color::Yellow,
+        "dark_yellow" => Color::DarkYellow,
+        "blue" => Color::Blue,
+        "dark_blue" => Color::DarkBlue,
+        "magenta" => Color::Magenta,
+        "dark_magenta" => Color::DarkMagenta,
+        "cyan" => Color::Cyan,
+        "dark_cyan" => Color::DarkCyan,
+        "white" => Color::White,
+        "grey" => Color::Grey,
+        _ => return Err(invalid(format!("invalid color `{}`", value))),
+    };
+    Ok(color)
+}
+
+#[cfg(test)]
+mod test_super {
+    use super::*;
+
+    #[test]
+    fn test_builtin_themes_parse() {
+        for name in Theme::builtin_names() {
+            let source = BUILTIN_THEMES
+                .iter()
+                .find(|(builtin_name, _)| *builtin_name == name)
+                .map(|(_, source)| *source)
+                .unwrap();
+            assert_eq!(Theme::from_toml(source, "x").unwrap().name(), name);
+        }
+    }
+
+    #[test]
+    fn test_from_toml() {
+        let theme = Theme::from_toml(
+            "[ui]\ntext = { fg = \"white\", bg = \"16\" }\n\
+             [syntax]\nstring = { fg = \"#ff8000\", bold = true }\n",
+            "custom",
+        )
+        .unwrap();
+        assert_eq!(theme.name(), "custom");
+        let string = theme.syntax(highlighting::Type::String);
+        assert_eq!(string.fg, Some(Color::Rgb { r: 255, g: 128, b: 0 }));
+        assert_eq!(string.bg, Some(Color::AnsiValue(16)));
+        assert!(string.bold);
+        assert_eq!(theme.syntax(highlighting::Type::Number), theme.text());
+
+        assert!(Theme::from_toml("[ui]\nsidebar = {}\n", "x").is_err());
+        assert!(Theme::from_toml("[syntax]\nstring = { fg = \"#12\" }\n", "x").is_err());
+    }
+}
@@ -0,0 +1,203 @@
+use crate::highlighting::Type;
+use crate::Row;
+use std::ops::Range;
+use tree_sitter::{InputEdit, Node, Parser, Point, Tree};
+
+/// A parse of a whole document, kept up to date by re-parsing
+/// incrementally whenever its text changes.
+pub struct SyntaxTree {
+    parser: Parser,
+    tree: Option<Tree>,
+}
+
+impl SyntaxTree {
+    /// A parser for the file type named `file_type`, if tree-sitter has a
+    /// grammar for it.
+    pub fn for_file_type(file_type: &str) -> Option<Self> {
+        let language = match file_type {
+            "Rust" => tree_sitter_rust::LANGUAGE.into(),
+            _ => return None,
+        };
+        let mut parser = Parser::new();
+        parser.set_language(&language).ok()?;
+        Some(Self { parser, tree: None })
+    }
+    /// Adjusts the tree to an edit of the text, so that the next `parse`
+    /// only has to look at what changed.
+    pub fn edit(&mut self, edit: &InputEdit) {
+        if let Some(tree) = &mut self.tree {
+            tree.edit(edit);
+        }
+    }
+    /// Parses `rows`, joined by newlines, reusing the old tree for the parts
+    /// that were not edited. Returns the first row whose syntax changed
+    /// outside of the edits, if any.
+    pub fn parse(&mut self, rows: &[Row]) -> Option<usize> {
+        let mut read = |_, point: Point| {
+            let rest = rows
+                .get(point.row)
+                .and_then(|row| row.string.as_bytes().get(point.column..))
+                .unwrap_or_default();
+            if rest.is_empty() && point.row.saturating_add(1) < rows.len() {
+                &b"\n"[..]
+            } else {
+                rest
+            }
+        };
+        let tree = self.parser.parse_with(&mut read, self.tree.as_ref());
+        let first = match (&self.tree, &tree) {
+            (Some(old), Some(new)) => old
+                .changed_ranges(new)
+                .map(|range| range.start_point.row)
+                .min(),
+            _ => Some(0),
+        };
+        self.tree = tree;
+        first
+    }
+    /// The highlighting type of every byte of the text in `range`.
+    pub fn highlight(&self, range: Range<usize>) -> Vec<Type> {
+        let mut types = vec![Type::None; range.len()];
+        if let Some(tree) = &self.tree {
+            paint(tree.root_node(), &range, &mut types);
+        }
+        types
+    }
+}
+
+/// The type of a node, and whether it covers its children too.
+fn node_type(node: Node) -> Option<(Type, bool)> {
+    let kind = node.kind();
+    if !node.is_named() {
+        let keyword = kind.trim_end_matches('!');
+        let is_keyword = keyword.starts_with(|c: char| c.is_ascii_alphabetic())
+            && keyword.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+        return if is_keyword {
+            Some((Type::PrimaryKeywords, true))
+        } else {
+            None
+        };
+    }
+    let hl_type = match kind {
+        "line_comment" | "block_comment" if node.child_by_field_name("doc").is_some() => {
+            (Type::DocComment, true)
+        }
+        "line_comment" => (Type::Comment, true),
+        "block_comment" => (Type::MultilineComment, true),
+        "string_literal" | "raw_string_literal" => (Type::String, true),
+        "char_literal" => (Type::Character, true),
+        "integer_literal" | "float_literal" => (Type::Number, true),
+        "boolean_literal" | "self" | "crate" | "super" | "mutable_specifier" => {
+            (Type::PrimaryKeywords, true)
+        }
+        "primitive_type" => (Type::SecondaryKeywords, true),
+        "type_identifier" => (Type::TypeName, true),
+        "lifetime" => (Type::Lifetime, true),
+        "attribute_item" | "inner_attribute_item" => (Type::Attribute, true),
+        _ => return None,
+    };
+    Some(hl_type)
+}
+
+/// Paints the bytes of `node` that fall in `range`, children over their
+/// parents.
+fn paint(node: Node, range: &Range<usize>, types: &mut [Type]) {
+    if node.end_byte() <= range.start || node.start_byte() >= range.end {
+        return;
+    }
+    if let Some((hl_type, covers_children)) = node_type(node) {
+        fill(node.start_byte()..node.end_byte(), hl_type, range, types);
+        if covers_children {
+            return;
+        }
+    }
+    let mut cursor = node.walk();
+    for child in node.children(&mut cursor) {
+        paint(child, range, types);
+    }
+    match node.kind() {
+        "function_item" | "function_signature_item" => {
+            if let Some(name) = node.child_by_field_name("name") {
+                fill(name.start_byte()..name.end_byte(), Type::Function, range, types);
+            }
+        }
+        "call_expression" => {
+            if let Some(name) = node.child_by_field_name("function").and_then(callee_name) {
+                fill(name.start_byte()..name.end_byte(), Type::Function, range, types);
+            }
+        }
+        "macro_invocation" => {
+            if let Some(name) = node.child_by_field_name("macro") {
+                // The `!` after the name belongs to the macro too.
+                let end = name.end_byte().saturating_add(1).min(node.end_byte());
+                fill(name.start_byte()..end, Type::Macro, range, types);
+            }
+        }
+        _ => (),
+    }
+}
+
+/// The identifier naming the function called by `function`, as in `f()`,
+/// `a.f()` or `a::f()`.
+fn callee_name(function: Node) -> Option<Node> {
+    match function.kind() {
+        "identifier" => Some(function),
+        "field_expression" => function.child_by_field_name("field"),
+        "scoped_identifier" => function.child_by_field_name("name"),
+        "generic_function" => function.child_by_field_name("function").and_then(callee_name),
+        _ => None,
+    }
+}
+
+fn fill(bytes: Range<usize>, hl_type: Type, range: &Range<usize>, types: &mut [Type]) {
+    let start = bytes.start.max(range.start).saturating_sub(range.start);
+    let end = bytes.end.min(range.end).saturating_sub(range.start);
+    if let Some(types) = types.get_mut(start..end) {
+        for byte in types {
+            *byte = hl_type;
+        }
+    }
+}
+
+#[cfg(test)]
+mod test_super {
+    use super::*;
+
+    #[test]
+    fn test_highlight() {
+        let mut tree = SyntaxTree::for_file_type("Rust").unwrap();
+        assert!(SyntaxTree::for_file_type("Python").is_none());
+        let mut rows = vec![Row::from("#[test]"), Row::from("fn main() { println!(\"a\"); }")];
+        tree.parse(&rows);
+        let types = tree.highlight(0..38);
+        assert_eq!(types[0], Type::Attribute);
+        assert_eq!(types[8], Type::PrimaryKeywords);
+        assert_eq!(types[11], Type::Function);
+        assert_eq!(types[20], Type::Macro);
+        assert_eq!(types[27], Type::Macro);
+        assert_eq!(types[28], Type::None);
+        assert_eq!(types[30], Type::String);
+
+        // Re-parsing after an edit sees the new text.
+        rows[1] = Row::from("fn main() { let x: u8 = 1; }");
+        tree.edit(&InputEdit {
+            start_byte: 20,
+            old_end_byte: 34,
+            new_end_byte: 34,
+            start_position: Point { row: 1, column: 12 },
+            old_end_position: Point { row: 1, column: 26 },
+            new_end_position: Point { row: 1, column: 26 },
+        });
+        tree.parse(&rows);
+        let types = tree.highlight(20..33);
+        assert_eq!(types[..4], [Type::PrimaryKeywords, Type::PrimaryKeywords, Type::PrimaryKeywords, Type::None]);
+        assert_eq!(types[7..9], [Type::SecondaryKeywords, Type::SecondaryKeywords]);
+        assert_eq!(types[12], Type::Number);
+
+        let mut tree = SyntaxTree::for_file_type("Rust").unwrap();
+        tree.parse(&[Row::from("/// a"), Row::from("// b")]);
+        let types = tree.highlight(0..10);
+        assert_eq!(types[..5], [Type::DocComment; 5]);
+        assert_eq!(types[6..], [Type::Comment; 4]);
+    }
+}
@@ -0,0 +1,452 @@
+use crate::keymap::Key;
+use crossterm::event::{KeyCode, KeyModifiers};
+
+/// The modes of the Vim keymap.
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum Mode {
+    Normal,
+    Insert,
+    Visual,
+    VisualLine,
+}
+
+impl Mode {
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Normal => "NORMAL",
+            Self::Insert => "INSERT",
+            Self::Visual => "VISUAL",
+            Self::VisualLine => "V-LINE",
+        }
+    }
+    pub fn is_visual(self) -> bool {
+        self == Self::Visual || self == Self::VisualLine
+    }
+}
+
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum Motion {
+    Left,
+    Right,
+    Up,
+    Down,
+    WordForward,
+    WordBackward,
+    WordEnd,
+    LineStart,
+    LineEnd,
+    FirstLine,
+    LastLine,
+    FindForward(char),
+    FindBackward(char),
+    TillForward(char),
+    TillBackward(char),
+    /// The current line, as in `dd` or `yy`.
+    CurrentLine,
+}
+
+impl Motion {
+    /// Whether an operator with this motion works on whole lines.
+    pub fn is_linewise(self) -> bool {
+        matches!(
+            self,
+            Self::Up | Self::Down | Self::FirstLine | Self::LastLine | Self::CurrentLine
+        )
+    }
+    /// Whether an operator with this motion includes the grapheme the motion
+    /// ends on.
+    pub fn is_inclusive(self) -> bool {
+        matches!(self, Self::WordEnd | Self::FindForward(_) | Self::TillForward(_))
+    }
+}
+
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum Operator {
+    Delete,
+    Change,
+    Yank,
+}
+
+/// Where `i`, `a`, `I`, `A`, `o` and `O` start inserting.
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum Insert {
+    Before,
+    After,
+    LineStart,
+    LineEnd,
+    LineBelow,
+    LineAbove,
+}
+
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum Action {
+    Move(Motion, Option<usize>),
+    Operate(Operator, Motion, Option<usize>),
+    Insert(Insert),
+    /// Switches to the given visual mode, or back to normal mode when it is
+    /// already active.
+    Visual(Mode),
+    /// Applies an operator to the selection of the given visual mode.
+    VisualOperate(Operator, Mode),
+    Paste { before: bool, count: usize },
+    /// Repeats the last change, as `.` does, with a new count replacing the
+    /// one it was made with.
+    Repeat(Option<usize>),
+    /// Opens the `:` command line.
+    CommandLine,
+    /// Starts recording a macro into a register, as `q{register}` does.
+    Record(char),
+    /// Stops recording, as `q` does while a macro is being recorded.
+    StopRecording,
+    /// Plays the macro in a register a number of times; `@@` plays the
+    /// last one again.
+    Play(char, usize),
+    /// Leaves insert mode.
+    Escape,
+    /// Not a Vim key; handled by the regular keymap.
+    Passthrough,
+}
+
+/// Text deleted or yanked by an operator, for `p` and `P`.
+#[derive(Clone)]
+pub struct Register {
+    pub text: String,
+    /// Whether the text is made of whole lines.
+    pub linewise: bool,
+}
+
+/// Turns key presses into Vim actions, keeping track of the mode, pending
+/// counts and operators, and the keys of the last change for `.`.
+pub struct Vim {
+    mode: Mode,
+    count: Option<usize>,
+    operator: Option<(Operator, Option<usize>)>,
+    /// A key waiting for its argument, such as `g`, `f` or `t`.
+    pending: Option<char>,
+    /// Keys of the command being typed, without its count.
+    keys: Vec<Key>,
+    /// Whether `keys` belong to a change that continues in insert mode.
+    changing: bool,
+    /// The count of the change being typed.
+    change_count: Option<usize>,
+    last_change: Vec<Key>,
+    last_count: Option<usize>,
+    /// Whether a macro is being recorded, which makes `q` stop it.
+    recording: bool,
+}
+
+impl Default for Vim {
+    fn default() -> Self {
+        Self {
+            mode: Mode::Normal,
+            count: None,
+            operator: None,
+            pending: None,
+            keys: Vec::new(),
+            changing: false,
+            change_count: None,
+            last_change: Vec::new(),
+            last_count: None,
+            recording: false,
+        }
+    }
+}
+
+impl Vim {
+    pub fn mode(&self) -> Mode {
+        self.mode
+    }
+    pub fn set_mode(&mut self, mode: Mode) {
+        if self.mode == Mode::Insert && mode != Mode::Insert && self.changing {
+            self.last_change = std::mem::take(&mut self.keys);
+            self.last_count = self.change_count.take();
+            self.changing = false;
+        }
+        self.mode = mode;
+    }
+    pub fn set_recording(&mut self, recording: bool) {
+        self.recording = recording;
+    }
+    /// The keys that repeat the last change with `count`, or with the count
+    /// it was made with.
+    pub fn repeat_keys(&self, count: Option<usize>) -> Vec<Key> {
+        let count = count.or(self.last_count).map_or_else(String::new, |count| count.to_string());
+        count
+            .chars()
+            .map(|digit| Key::new(KeyCode::Char(digit), KeyModifiers::NONE))
+            .chain(self.last_change.iter().copied())
+            .collect()
+    }
+    /// Whether a count, operator or multi-key command is being typed.
+    pub fn is_pending(&self) -> bool {
+        self.count.is_some() || self.operator.is_some() || self.pending.is_some()
+    }
+    fn reset(&mut self) {
+        self.count = None;
+        self.operator = None;
+        self.pending = None;
+    }
+    /// Feeds one key press and returns the resulting action, or `None` while
+    /// a command is incomplete or when the key does nothing.
+    pub fn feed(&mut self, key: Key) -> Option<Action> {
+        self.keys.push(key);
+        if self.mode == Mode::Insert {
+            if key.code() == KeyCode::Esc {
+                self.set_mode(Mode::Normal);
+                return Some(Action::Escape);
+            }
+            return Some(Action::Passthrough);
+        }
+        let action = self.feed_normal(key);
+        match action {
+            None if self.is_pending() => (),
+            Some(Action::Operate(Operator::Change, _, count)) => {
+                self.changing = true;
+                self.change_count = count;
+                self.mode = Mode::Insert;
+            }
+            Some(Action::VisualOperate(Operator::Change, _)) | Some(Action::Insert(_)) => {
+                self.changing = true;
+                self.change_count = None;
+                self.mode = Mode::Insert;
+            }
+            Some(Action::Operate(Operator::Delete, _, count)) => {
+                self.last_change = std::mem::take(&mut self.keys);
+                self.last_count = count;
+            }
+            Some(Action::Paste { count, .. }) => {
+                self.last_change = std::mem::take(&mut self.keys);
+                self.last_count = Some(count);
+            }
+            _ => self.keys.clear(),
+        }
+        action
+    }
+    fn feed_normal(&mut self, key: Key) -> Option<Action> {
+        let c = match key.code() {
+            KeyCode::Esc => {
+                self.reset();
+                if self.mode.is_visual() {
+                    let mode = self.mode;
+                    self.mode = Mode::Normal;
+                    return Some(Action::Visual(mode));
+                }
+                return None;
+            }
+            KeyCode::Char(c) if key.is_text() => c,
+            KeyCode::Enter if key.modifiers().is_empty() => '+',
+            KeyCode::Backspace if key.modifiers().is_empty() => 'h',
+            KeyCode::Delete if key.modifiers().is_empty() => 'x',
+            _ => {
+                self.reset();
+                return Some(Action::Passthrough);
+            }
+        };
+        if let Some(pending) = self.pending.take() {
+            let motion = match pending {
+                'q' => return Some(Action::Record(c)),
+                '@' => {
+                    let times = self.count.take().unwrap_or(1);
+                    return Some(Action::Play(c, times));
+                }
+                'g' if c == 'g' => Motion::FirstLine,
+                'f' => Motion::FindForward(c),
+                'F' => Motion::FindBackward(c),
+                't' => Motion::TillForward(c),
+                'T' => Motion::TillBackward(c),
+                _ => {
+                    self.reset();
+                    return None;
+                }
+            };
+            return Some(self.motion(motion));
+        }
+        if let Some(digit) = c.to_digit(10) {
+            if digit > 0 || self.count.is_some() {
+                // Counts are kept apart from the keys, so that `.` can
+                // replace them.
+                self.keys.pop();
+                let count = self.count.unwrap_or(0).saturating_mul(10);
+                self.count = Some(count.saturating_add(digit as usize));
+                return None;
+            }
+        }
+        let count = self.count.take();
+        let times = count.unwrap_or(1);
+        let action = match c {
+            'h' => self.motion_with(Motion::Left, count),
+            'j' | '+' => self.motion_with(Motion::Down, count),
+            'k' => self.motion_with(Motion::Up, count),
+            'l' | ' ' => self.motion_with(Motion::Right, count),
+            'w' => self.motion_with(Motion::WordForward, count),
+            'b' => self.motion_with(Motion::WordBackward, count),
+            'e' => self.motion_with(Motion::WordEnd, count),
+            '0' => self.motion_with(Motion::LineStart, count),
+            '$' => self.motion_with(Motion::LineEnd, count),
+            'G' => self.motion_with(Motion::LastLine, count),
+            'g' | 'f' | 'F' | 't' | 'T' => {
+                self.count = count;
+                self.pending = Some(c);
+                return None;
+            }
+            'q' if self.recording => Action::StopRecording,
+            'q' | '@' if self.operator.is_none() => {
+                self.count = count;
+                self.pending = Some(c);
+                return None;
+            }
+            'd' | 'c' | 'y' => {
+                let operator = match c {
+                    'd' => Operator::Delete,
+                    'c' => Operator::Change,
+                    _ => Operator::Yank,
+                };
+                if self.mode.is_visual() {
+                    let mode = self.mode;
+                    self.mode = Mode::Normal;
+                    return Some(Action::VisualOperate(operator, mode));
+                }
+                match self.operator.take() {
+                    Some((pending, outer)) if pending == operator => {
+                        Action::Operate(operator, Motion::CurrentLine, multiply(outer, count))
+                    }
+                    Some(_) => {
+                        self.reset();
+                        return None;
+                    }
+                    None => {
+                        self.operator = Some((operator, count));
+                        return None;
+                    }
+                }
+            }
+            'x' if self.mode.is_visual() => {
+                let mode = self.mode;
+                self.mode = Mode::Normal;
+                Action::VisualOperate(Operator::Delete, mode)
+            }
+            'x' => Action::Operate(Operator::Delete, Motion::Right, count),
+            'X' => Action::Operate(Operator::Delete, Motion::Left, count),
+            'D' => Action::Operate(Operator::Delete, Motion::LineEnd, None),
+            'C' => Action::Operate(Operator::Change, Motion::LineEnd, None),
+            'Y' => Action::Operate(Operator::Yank, Motion::CurrentLine, count),
+            'i' => Action::Insert(Insert::Before),
+            'a' => Action::Insert(Insert::After),
+            'I' => Action::Insert(Insert::LineStart),
+            'A' => Action::Insert(Insert::LineEnd),
+            'o' => Action::Insert(Insert::LineBelow),
+            'O' => Action::Insert(Insert::LineAbove),
+            'v' | 'V' if self.operator.is_none() => {
+                let mode = if c == 'v' { Mode::Visual } else { Mode::VisualLine };
+                self.mode = if self.mode == mode { Mode::Normal } else { mode };
+                Action::Visual(mode)
+            }
+            'p' => Action::Paste { before: false, count: times },
+            'P' => Action::Paste { before: true, count: times },
+            '.' => Action::Repeat(count),
+            ':' => Action::CommandLine,
+            _ => {
+                self.reset();
+                return None;
+            }
+        };
+        if self.operator.is_some() && !matches!(action, Action::Move(..)) {
+            self.reset();
+            return None;
+        }
+        Some(action)
+    }
+    fn motion(&mut self, motion: Motion) -> Action {
+        let count = self.count.take();
+        self.motion_with(motion, count)
+    }
+    /// Turns a motion into a move, or into an operation when an operator
+    /// is pending.
+    fn motion_with(&mut self, motion: Motion, count: Option<usize>) -> Action {
+        match self.operator.take() {
+            Some((operator, outer)) => Action::Operate(operator, motion, multiply(outer, count)),
+            None => Action::Move(motion, count),
+        }
+    }
+}
+
+/// Combines the counts before and after an operator, as in `2d3w`.
+fn multiply(outer: Option<usize>, inner: Option<usize>) -> Option<usize> {
+    match (outer, inner) {
+        (Some(outer), Some(inner)) => Some(outer.saturating_mul(inner)),
+        (outer, None) => outer,
+        (None, inner) => inner,
+    }
+}
+
+#[cfg(test)]
+mod test_super {
+    use super::*;
+
+    fn keys(text: &str) -> Vec<Key> {
+        text.chars()
+            .map(|c| Key::new(KeyCode::Char(c), KeyModifiers::NONE))
+            .collect()
+    }
+
+    fn feed_str(vim: &mut Vim, text: &str) -> Vec<Action> {
+        keys(text).into_iter().filter_map(|key| vim.feed(key)).collect()
+    }
+
+    #[test]
+    fn test_counts_and_operators() {
+        let mut vim = Vim::default();
+        assert_eq!(
+            feed_str(&mut vim, "3w2d3wdd0gg5G"),
+            vec![
+                Action::Move(Motion::WordForward, Some(3)),
+                Action::Operate(Operator::Delete, Motion::WordForward, Some(6)),
+                Action::Operate(Operator::Delete, Motion::CurrentLine, None),
+                Action::Move(Motion::LineStart, None),
+                Action::Move(Motion::FirstLine, None),
+                Action::Move(Motion::LastLine, Some(5)),
+            ]
+        );
+        assert_eq!(
+            feed_str(&mut vim, "dt;yfxdy"),
+            vec![
+                Action::Operate(Operator::Delete, Motion::TillForward(';'), None),
+                Action::Operate(Operator::Yank, Motion::FindForward('x'), None),
+            ]
+        );
+        assert!(!vim.is_pending());
+    }
+
+    #[test]
+    fn test_modes_and_repeat() {
+        let mut vim = Vim::default();
+        assert_eq!(feed_str(&mut vim, "cw"), vec![Action::Operate(Operator::Change, Motion::WordForward, None)]);
+        assert_eq!(vim.mode(), Mode::Insert);
+        assert_eq!(feed_str(&mut vim, "ab"), vec![Action::Passthrough, Action::Passthrough]);
+        assert_eq!(vim.feed(Key::new(KeyCode::Esc, KeyModifiers::NONE)), Some(Action::Escape));
+        assert_eq!(vim.mode(), Mode::Normal);
+        assert_eq!(vim.repeat_keys(None).len(), 5);
+
+        feed_str(&mut vim, "jx");
+        assert_eq!(vim.repeat_keys(None), keys("x"));
+        assert_eq!(feed_str(&mut vim, "2."), vec![Action::Repeat(Some(2))]);
+        feed_str(&mut vim, "2d3w");
+        assert_eq!(vim.repeat_keys(None), keys("6dw"));
+        assert_eq!(vim.repeat_keys(Some(4)), keys("4dw"));
+
+        assert_eq!(feed_str(&mut vim, "vd"), vec![Action::Visual(Mode::Visual), Action::VisualOperate(Operator::Delete, Mode::Visual)]);
+        assert_eq!(vim.mode(), Mode::Normal);
+        assert_eq!(
+            vim.feed(Key::new(KeyCode::Char('s'), KeyModifiers::CONTROL)),
+            Some(Action::Passthrough)
+        );
+
+        assert_eq!(feed_str(&mut vim, "qa"), vec![Action::Record('a')]);
+        vim.set_recording(true);
+        assert_eq!(feed_str(&mut vim, "q3@a@@"), vec![
+            Action::StopRecording,
+            Action::Play('a', 3),
+            Action::Play('@', 1),
+        ]);
+    }
+}
@@ -0,0 +1,473 @@
+use crossterm::event::{Event, KeyCode, KeyModifiers};
+use hecto::{Config, Document, Editor, TestBackend, Terminal};
+
+fn editor_with_config(backend: &TestBackend, config: Config) -> Editor {
+    let terminal = Terminal::new(Box::new(backend.clone())).expect("test terminal");
+    Editor::new(terminal, Document::default(), config)
+}
+
+fn editor(backend: &TestBackend) -> Editor {
+    editor_with_config(backend, Config::default())
+}
+
+fn process_all(editor: &mut Editor, backend: &TestBackend) {
+    while backend.pending_events() > 0 {
+        editor.process_keypress().expect("scripted event");
+    }
+    editor.refresh_screen().expect("refresh");
+}
+
+#[test]
+fn test_typing_is_drawn() {
+    let backend = TestBackend::new(20, 6);
+    let mut editor = editor(&backend);
+    backend.type_str("ab中c");
+    backend.push_key(KeyCode::Enter, KeyModifiers::NONE);
+    backend.type_str("d");
+    process_all(&mut editor, &backend);
+
+    assert_eq!(backend.line(0), "ab中c               ");
+    assert_eq!(backend.line(1), "d                   ");
+    assert_eq!(backend.line(2), "~                   ");
+    assert!(backend.line(4).starts_with("[No Name] - 2 lines"));
+    assert_eq!(backend.cursor(), (1, 1));
+    assert!(backend.cursor_visible());
+}
+
+#[test]
+fn test_paste_is_one_edit() {
+    let backend = TestBackend::new(20, 6);
+    let mut editor = editor(&backend);
+    backend.type_str("{}");
+    backend.push_key(KeyCode::Left, KeyModifiers::NONE);
+    backend.push_event(Event::Paste(String::from("\n    x\n")));
+    process_all(&mut editor, &backend);
+
+    let rows: Vec<&str> = (0..editor.document().len())
+        .filter_map(|index| editor.document().row(index))
+        .map(|row| row.string.as_str())
+        .collect();
+    assert_eq!(rows, vec!["{", "    x", "}"]);
+    assert_eq!(backend.line(1), "    x               ");
+    assert_eq!(backend.cursor(), (0, 2));
+}
+
+#[test]
+fn test_quit_requires_confirmation_when_dirty() {
+    let backend = TestBackend::new(80, 6);
+    let mut editor = editor(&backend);
+    backend.type_str("a");
+    backend.push_key(KeyCode::Char('q'), KeyModifiers::CONTROL);
+    process_all(&mut editor, &backend);
+    assert!(!editor.should_quit());
+    assert!(backend.line(5).starts_with("WARNING! File has unsaved changes."));
+
+    for _ in 0..3 {
+        backend.push_key(KeyCode::Char('q'), KeyModifiers::CONTROL);
+    }
+    process_all(&mut editor, &backend);
+    assert!(editor.should_quit());
+}
+
+#[test]
+fn test_resize_redraws() {
+    let backend = TestBackend::new(20, 6);
+    let mut editor = editor(&backend);
+    backend.type_str("hello");
+    process_all(&mut editor, &backend);
+
+    backend.resize(10, 4);
+    process_all(&mut editor, &backend);
+    assert_eq!(backend.line(0), "hello     ");
+    assert!(backend.line(2).starts_with("[No Name]"));
+}
+
+#[test]
+fn test_switch_theme() {
+    let backend = TestBackend::new(40, 6);
+    let mut editor = editor(&backend);
+    backend.push_key(KeyCode::Char('t'), KeyModifiers::CONTROL);
+    backend.type_str("light");
+    backend.push_key(KeyCode::Enter, KeyModifiers::NONE);
+    process_all(&mut editor, &backend);
+    assert_eq!(editor.theme().name(), "light");
+    assert!(backend.line(5).starts_with("Switched to theme light."));
+
+    backend.push_key(KeyCode::Char('t'), KeyModifiers::CONTROL);
+    backend.type_str("no-such-theme");
+    backend.push_key(KeyCode::Enter, KeyModifiers::NONE);
+    process_all(&mut editor, &backend);
+    assert_eq!(editor.theme().name(), "light");
+    assert!(backend.line(5).starts_with("ERR: Could not load theme"));
+}
+
+#[test]
+fn test_line_numbers_and_tabs() {
+    let backend = TestBackend::new(20, 6);
+    let mut config = Config::default();
+    config.line_numbers = true;
+    let mut editor = editor_with_config(&backend, config);
+    backend.push_event(Event::Paste(String::from("a\tb\n\tc")));
+    process_all(&mut editor, &backend);
+
+    assert_eq!(backend.line(0), "1 a   b             ");
+    assert_eq!(backend.line(1), "2     c             ");
+    assert_eq!(backend.cursor(), (7, 1));
+}
+
+#[test]
+fn test_cursor_line_and_rulers() {
+    let backend = TestBackend::new(20, 6);
+    let mut config = Config::default();
+    config.cursor_line = true;
+    config.rulers = vec![5];
+    let mut editor = editor_with_config(&backend, config);
+    backend.type_str("ab");
+    backend.push_key(KeyCode::Enter, KeyModifiers::NONE);
+    backend.type_str("cd");
+    process_all(&mut editor, &backend);
+
+    let plain = backend.cell(0, 0).style;
+    let cursor_line = backend.cell(0, 1).style;
+    assert_ne!(cursor_line, plain);
+    assert_eq!(backend.cell(19, 1).style, cursor_line);
+    // The ruler is on the 5th column itself, like Vim's `colorcolumn`.
+    for y in [0, 2, 3] {
+        assert_ne!(backend.cell(4, y).style, backend.cell(3, y).style);
+        assert_ne!(backend.cell(4, y).style, backend.cell(5, y).style);
+    }
+    assert_eq!(backend.cell(3, 2).style, plain);
+    assert_eq!(backend.cell(5, 2).style, plain);
+}
+
+#[test]
+fn test_rulers_on_wrapped_lines() {
+    let backend = TestBackend::new(10, 6);
+    let mut config = Config::default();
+    config.soft_wrap = true;
+    config.rulers = vec![13];
+    let mut editor = editor_with_config(&backend, config);
+    backend.type_str("0123456789abcdef");
+    process_all(&mut editor, &backend);
+
+    assert_eq!(backend.line(1), "abcdef    ");
+    let plain = backend.cell(0, 0).style;
+    // Column 13 is the third cell of the row's second screen line.
+    assert_ne!(backend.cell(2, 1).style, plain);
+    assert_eq!(backend.cell(1, 1).style, plain);
+    assert_eq!(backend.cell(3, 1).style, plain);
+    for x in 0..10 {
+        assert_eq!(backend.cell(x, 0).style, plain);
+    }
+}
+
+#[test]
+fn test_soft_wrap() {
+    let backend = TestBackend::new(10, 6);
+    let mut config = Config::default();
+    config.soft_wrap = true;
+    let mut editor = editor_with_config(&backend, config);
+    backend.type_str("0123456789abcdefghij中");
+    backend.push_key(KeyCode::Enter, KeyModifiers::NONE);
+    backend.type_str("x");
+    process_all(&mut editor, &backend);
+
+    assert_eq!(backend.line(0), "0123456789");
+    assert_eq!(backend.line(1), "abcdefghij");
+    assert_eq!(backend.line(2), "中        ");
+    assert_eq!(backend.line(3), "x         ");
+    assert_eq!(backend.cursor(), (1, 3));
+}
+
+#[test]
+fn test_remapped_chord() {
+    let backend = TestBackend::new(80, 6);
+    let mut config = Config::default();
+    config
+        .keybindings
+        .insert(String::from("Ctrl-X Ctrl-C"), String::from("quit"));
+    config
+        .keybindings
+        .insert(String::from("Ctrl-Q"), String::from("none"));
+    let mut editor = editor_with_config(&backend, config);
+    backend.type_str("ab cd");
+    backend.push_key(KeyCode::Left, KeyModifiers::CONTROL);
+    backend.push_key(KeyCode::Char('x'), KeyModifiers::CONTROL);
+    process_all(&mut editor, &backend);
+    assert_eq!(editor.cursor_position().x, 3);
+    assert!(backend.line(5).starts_with("Ctrl-X -"));
+
+    backend.push_key(KeyCode::Char('z'), KeyModifiers::CONTROL);
+    process_all(&mut editor, &backend);
+    assert!(backend.line(5).starts_with("Ctrl-X Ctrl-Z is not bound."));
+
+    backend.push_key(KeyCode::Char('x'), KeyModifiers::CONTROL);
+    backend.push_key(KeyCode::Char('c'), KeyModifiers::CONTROL);
+    process_all(&mut editor, &backend);
+    assert!(backend
+        .line(5)
+        .starts_with("WARNING! File has unsaved changes. Press Ctrl-X Ctrl-C 3 more times"));
+}
+
+#[test]
+fn test_help_screen() {
+    let backend = TestBackend::new(80, 8);
+    let mut editor = editor(&backend);
+    backend.type_str("x");
+    backend.push_key(KeyCode::F(1), KeyModifiers::NONE);
+    process_all(&mut editor, &backend);
+    assert!(backend.line(0).starts_with("Key bindings"));
+    assert!(backend.line(1).starts_with("  Ctrl-Q"));
+
+    backend.push_key(KeyCode::Down, KeyModifiers::NONE);
+    process_all(&mut editor, &backend);
+    assert!(backend.line(0).starts_with("  Ctrl-Q"));
+
+    backend.push_key(KeyCode::Esc, KeyModifiers::NONE);
+    process_all(&mut editor, &backend);
+    assert!(backend.line(0).starts_with("x "));
+    assert_eq!(editor.document().row(0).unwrap().string, "x");
+}
+
+#[test]
+fn test_command_palette() {
+    let backend = TestBackend::new(70, 8);
+    let mut editor = editor(&backend);
+    backend.type_str("one two");
+    backend.push_key(KeyCode::Char('p'), KeyModifiers::CONTROL);
+    backend.type_str("word");
+    // The prompt gives up once the scripted events run out, which leaves the
+    // last frame drawn while it was open on screen.
+    while backend.pending_events() > 0 {
+        editor.process_keypress().expect("scripted event");
+    }
+    assert_eq!(
+        backend.line(4).trim_end(),
+        " move-word-left    Move to the previous word         Ctrl-Left, Alt-B"
+    );
+    assert!(backend.line(5).starts_with(" move-word-right"));
+    assert!(backend.line(7).starts_with("Command: word"));
+
+    backend.push_key(KeyCode::Char('p'), KeyModifiers::CONTROL);
+    backend.type_str("word");
+    backend.push_key(KeyCode::Down, KeyModifiers::NONE);
+    backend.push_key(KeyCode::Down, KeyModifiers::NONE);
+    backend.push_key(KeyCode::Up, KeyModifiers::NONE);
+    backend.push_key(KeyCode::Up, KeyModifiers::NONE);
+    backend.push_key(KeyCode::Enter, KeyModifiers::NONE);
+    process_all(&mut editor, &backend);
+    assert_eq!(editor.cursor_position().x, 4);
+    assert!(backend.line(4).starts_with("~"));
+
+    backend.push_key(KeyCode::Char('p'), KeyModifiers::CONTROL);
+    backend.type_str("quit");
+    backend.push_key(KeyCode::Enter, KeyModifiers::NONE);
+    process_all(&mut editor, &backend);
+    assert!(!editor.should_quit());
+    assert!(backend.line(7).starts_with("WARNING! File has unsaved changes."));
+}
+
+fn vim_editor(backend: &TestBackend, text: &str) -> Editor {
+    let config = Config {
+        vim: true,
+        ..Config::default()
+    };
+    let mut editor = editor_with_config(backend, config);
+    backend.push_event(Event::Paste(String::from(text)));
+    backend.type_str("gg");
+    process_all(&mut editor, backend);
+    editor
+}
+
+fn rows(editor: &Editor) -> Vec<String> {
+    (0..editor.document().len())
+        .filter_map(|index| editor.document().row(index))
+        .map(|row| row.string.clone())
+        .collect()
+}
+
+#[test]
+fn test_vim_operators_and_repeat() {
+    let backend = TestBackend::new(40, 8);
+    let mut editor = vim_editor(&backend, "one two three\nfour five\nsix");
+    assert!(backend.line(6).starts_with("NORMAL | [No Name] - 3 lines"));
+
+    backend.type_str("dw.j");
+    process_all(&mut editor, &backend);
+    assert_eq!(rows(&editor), vec!["three", "four five", "six"]);
+    assert_eq!(editor.cursor_position().y, 1);
+
+    backend.type_str("ddp");
+    process_all(&mut editor, &backend);
+    assert_eq!(rows(&editor), vec!["three", "six", "four five"]);
+    assert_eq!(editor.cursor_position().y, 2);
+
+    backend.type_str("wcwfive");
+    backend.push_key(KeyCode::Esc, KeyModifiers::NONE);
+    backend.type_str("0.");
+    process_all(&mut editor, &backend);
+    assert_eq!(rows(&editor), vec!["three", "six", "five five"]);
+    assert_eq!(editor.cursor_position().x, 3);
+    assert!(backend.line(6).starts_with("NORMAL |"));
+
+    backend.type_str("gg2yyGp");
+    process_all(&mut editor, &backend);
+    assert_eq!(rows(&editor), vec!["three", "six", "five five", "three", "six"]);
+
+    // A count given to `.` replaces the count of the change.
+    backend.type_str("Gpgg2dd");
+    process_all(&mut editor, &backend);
+    assert_eq!(rows(&editor), vec!["five five", "three", "six", "three", "six"]);
+    backend.type_str("3.");
+    process_all(&mut editor, &backend);
+    assert_eq!(rows(&editor), vec!["three", "six"]);
+}
+
+#[test]
+fn test_vim_motions_and_visual_mode() {
+    let backend = TestBackend::new(40, 8);
+    let mut editor = vim_editor(&backend, "let x = (a, b);\nfn main() {}");
+
+    backend.type_str("f(ldt)");
+    process_all(&mut editor, &backend);
+    assert_eq!(rows(&editor)[0], "let x = ();");
+
+    backend.type_str("0wve");
+    process_all(&mut editor, &backend);
+    assert!(backend.line(6).starts_with("VISUAL |"));
+    let plain = backend.cell(3, 0).style;
+    assert_ne!(backend.cell(4, 0).style, plain);
+    assert_ne!(backend.cell(6, 0).style, plain);
+    assert_eq!(backend.cell(7, 0).style, plain);
+
+    backend.type_str("y$p");
+    process_all(&mut editor, &backend);
+    assert_eq!(rows(&editor)[0], "let x = ();x =");
+
+    backend.type_str("jVx");
+    process_all(&mut editor, &backend);
+    assert_eq!(rows(&editor), vec!["let x = ();x ="]);
+
+    backend.type_str("A!");
+    backend.push_key(KeyCode::Esc, KeyModifiers::NONE);
+    process_all(&mut editor, &backend);
+    assert_eq!(rows(&editor), vec!["let x = ();x =!"]);
+    assert_eq!(editor.cursor_position().x, 14);
+}
+
+#[test]
+fn test_ex_command_line() {
+    let backend = TestBackend::new(40, 8);
+    let mut editor = vim_editor(&backend, "foo bar foo\nbaz foo\nqux");
+    backend.type_str(":%s/foo/x/g");
+    backend.push_key(KeyCode::Enter, KeyModifiers::NONE);
+    process_all(&mut editor, &backend);
+    assert_eq!(rows(&editor), vec!["x bar x", "baz x", "qux"]);
+    assert!(backend.line(7).starts_with("3 substitutions on 2 lines"));
+
+    backend.type_str(":1");
+    backend.push_key(KeyCode::Enter, KeyModifiers::NONE);
+    backend.type_str(":set nu");
+    backend.push_key(KeyCode::Enter, KeyModifiers::NONE);
+    process_all(&mut editor, &backend);
+    assert_eq!(editor.cursor_position().y, 0);
+    assert!(editor.config().line_numbers);
+    assert_eq!(backend.line(0), "1 x bar x                               ");
+
+    backend.type_str(":q");
+    backend.push_key(KeyCode::Enter, KeyModifiers::NONE);
+    process_all(&mut editor, &backend);
+    assert!(!editor.should_quit());
+    assert!(backend.line(7).starts_with("ERR: No write since last change"));
+
+    backend.type_str(":");
+    backend.push_key(KeyCode::Up, KeyModifiers::NONE);
+    backend.type_str("!");
+    backend.push_key(KeyCode::Enter, KeyModifiers::NONE);
+    process_all(&mut editor, &backend);
+    assert!(editor.should_quit());
+}
+
+#[test]
+fn test_prompt_editing_and_history() {
+    let backend = TestBackend::new(40, 6);
+    let mut editor = editor(&backend);
+    backend.push_event(Event::Paste(String::from("one\ntwo 中文\nthree")));
+    backend.push_key(KeyCode::Char('g'), KeyModifiers::CONTROL);
+    backend.type_str("13");
+    backend.push_key(KeyCode::Left, KeyModifiers::NONE);
+    backend.push_key(KeyCode::Backspace, KeyModifiers::NONE);
+    backend.push_key(KeyCode::Enter, KeyModifiers::NONE);
+    process_all(&mut editor, &backend);
+    assert_eq!(editor.cursor_position().y, 2);
+
+    backend.push_key(KeyCode::Char('g'), KeyModifiers::CONTROL);
+    backend.push_key(KeyCode::Up, KeyModifiers::NONE);
+    backend.push_key(KeyCode::Char('w'), KeyModifiers::CONTROL);
+    backend.type_str("1");
+    backend.push_key(KeyCode::Enter, KeyModifiers::NONE);
+    process_all(&mut editor, &backend);
+    assert_eq!(editor.cursor_position().y, 0);
+
+    backend.push_key(KeyCode::Char('f'), KeyModifiers::CONTROL);
+    backend.type_str("中文x");
+    backend.push_key(KeyCode::Backspace, KeyModifiers::NONE);
+    backend.push_key(KeyCode::Left, KeyModifiers::NONE);
+    while backend.pending_events() > 0 {
+        editor.process_keypress().expect("scripted event");
+    }
+    // The prompt is wider than the screen, so it scrolls with the cursor.
+    assert!(backend.line(5).ends_with("navigate): 中文"));
+    assert_eq!(backend.cursor(), (38, 5));
+
+    backend.push_key(KeyCode::Char('f'), KeyModifiers::CONTROL);
+    backend.type_str("中文");
+    backend.push_key(KeyCode::Enter, KeyModifiers::NONE);
+    process_all(&mut editor, &backend);
+    assert_eq!(editor.cursor_position().y, 1);
+    assert_eq!(editor.cursor_position().x_word_index, 4);
+
+    backend.push_key(KeyCode::Char('g'), KeyModifiers::CONTROL);
+    backend.push_key(KeyCode::Up, KeyModifiers::NONE);
+    backend.push_key(KeyCode::Up, KeyModifiers::NONE);
+    backend.push_key(KeyCode::Enter, KeyModifiers::NONE);
+    process_all(&mut editor, &backend);
+    assert_eq!(editor.cursor_position().y, 2);
+}
+
+#[test]
+fn test_vim_macros() {
+    let backend = TestBackend::new(40, 8);
+    let mut editor = vim_editor(&backend, "a\nb\nc\nd");
+    backend.type_str("qaA;");
+    backend.push_key(KeyCode::Esc, KeyModifiers::NONE);
+    backend.type_str("j");
+    process_all(&mut editor, &backend);
+    assert!(backend.line(6).starts_with("NORMAL | REC @a | [No Name]"));
+    backend.type_str("q2@a");
+    process_all(&mut editor, &backend);
+    assert_eq!(rows(&editor), vec!["a;", "b;", "c;", "d"]);
+    backend.type_str("@@");
+    process_all(&mut editor, &backend);
+    assert_eq!(rows(&editor), vec!["a;", "b;", "c;", "d;"]);
+}
+
+#[test]
+fn test_macros() {
+    let backend = TestBackend::new(40, 6);
+    let mut editor = editor(&backend);
+    backend.push_key(KeyCode::Char('k'), KeyModifiers::CONTROL);
+    backend.push_key(KeyCode::Char('r'), KeyModifiers::CONTROL);
+    backend.type_str("b");
+    backend.push_key(KeyCode::Enter, KeyModifiers::NONE);
+    backend.type_str("xy");
+    backend.push_key(KeyCode::Left, KeyModifiers::NONE);
+    backend.push_key(KeyCode::Char('k'), KeyModifiers::CONTROL);
+    backend.push_key(KeyCode::Char('r'), KeyModifiers::CONTROL);
+    backend.push_key(KeyCode::Char('k'), KeyModifiers::CONTROL);
+    backend.push_key(KeyCode::Char('p'), KeyModifiers::CONTROL);
+    backend.type_str("2b");
+    backend.push_key(KeyCode::Enter, KeyModifiers::NONE);
+    process_all(&mut editor, &backend);
+    assert_eq!(rows(&editor), vec!["xxxyyy"]);
+}